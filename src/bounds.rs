@@ -0,0 +1,274 @@
+use cgmath::{
+    EuclideanSpace, InnerSpace, Matrix, Matrix4, MetricSpace, Point3, Transform, Vector3, Vector4,
+};
+
+use crate::model::ModelVertex;
+
+/// Axis aligned bounding box
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    /// The smallest box holding every point (a point at the origin when there are none)
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self {
+                min: Point3::origin(),
+                max: Point3::origin(),
+            };
+        };
+        points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: Point3::new(
+                    aabb.min.x.min(point.x),
+                    aabb.min.y.min(point.y),
+                    aabb.min.z.min(point.z),
+                ),
+                max: Point3::new(
+                    aabb.max.x.max(point.x),
+                    aabb.max.y.max(point.y),
+                    aabb.max.z.max(point.z),
+                ),
+            },
+        )
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    // Half of the size along each axis
+    pub fn extents(&self) -> Vector3<f32> {
+        (self.max - self.min) / 2.0
+    }
+
+    /// The box holding this one once transformed by `matrix` (an affine transform)
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        // Each axis of the new box gets the extents projected on it (Arvo's method)
+        let center = matrix.transform_point(self.center());
+        let extents = self.extents();
+        let row_extent = |row: Vector4<f32>| row.truncate().map(f32::abs).dot(extents);
+        let extents = Vector3::new(
+            row_extent(matrix.row(0)),
+            row_extent(matrix.row(1)),
+            row_extent(matrix.row(2)),
+        );
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// Sphere around a set of points, cheaper to test than a box but looser
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// The sphere around `points` centered on their box
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>> + Clone) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points
+            .into_iter()
+            .map(|point| point.distance2(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    /// The sphere holding this one once transformed by `matrix` (an affine transform),
+    /// the radius grows with the largest scale
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude2()
+            .max(matrix.y.truncate().magnitude2())
+            .max(matrix.z.truncate().magnitude2())
+            .sqrt();
+        Self {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// Bounds of a mesh in model space, computed when it is loaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_vertices(vertices: &[ModelVertex]) -> Self {
+        let points = vertices.iter().map(|vertex| Point3::from(vertex.position));
+        Self {
+            aabb: Aabb::from_points(points.clone()),
+            sphere: BoundingSphere::from_points(points),
+        }
+    }
+}
+
+/// The six planes of the volume a camera sees, facing inwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    // Normal in xyz, distance to the origin in w
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Planes of a view projection matrix, for wgpu's clip space (z from 0 to 1)
+    /// (Gribb & Hartmann)
+    pub fn from_view_proj(view_proj: &Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 {
+                plane / length
+            } else {
+                plane
+            }
+        });
+        Self { planes }
+    }
+
+    // Signed distance of a point to a plane, positive inside
+    fn distance(plane: Vector4<f32>, point: Point3<f32>) -> f32 {
+        plane.truncate().dot(point.to_vec()) + plane.w
+    }
+
+    /// Whether the sphere is at least partly inside
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|&plane| Frustum::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    /// Whether the box is at least partly inside (some boxes near the corners pass too)
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|&plane| {
+            // The corner the farthest along the normal
+            let corner = Point3::new(
+                if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            Frustum::distance(plane, corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    use super::*;
+    use crate::camera::{Camera, CameraUniform, Projection};
+
+    // At the origin looking down -Z, 90° wide and high, from 0.1 to 100
+    fn frustum() -> Frustum {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        let projection = Projection::new(100, 100, Deg(90.0), 0.1, 100.0);
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera, &projection);
+        uniform.frustum()
+    }
+
+    fn sphere(center: [f32; 3], radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: center.into(),
+            radius,
+        }
+    }
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb {
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    fn assert_near(a: Point3<f32>, b: Point3<f32>) {
+        assert!(a.distance(b) < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn spheres() {
+        let frustum = frustum();
+        // Inside
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -5.0], 1.0)));
+        // Behind, past the far plane and off to the side
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, 5.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, -200.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([-8.0, 0.0, -5.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 8.0, -5.0], 1.0)));
+        // Across the left, top, near and far planes
+        assert!(frustum.intersects_sphere(&sphere([-5.5, 0.0, -5.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 5.5, -5.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, 0.5], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -100.5], 1.0)));
+    }
+
+    #[test]
+    fn boxes() {
+        let frustum = frustum();
+        // Inside
+        assert!(frustum.intersects_aabb(&aabb([-0.5, -0.5, -5.5], [0.5, 0.5, -4.5])));
+        // Behind, past the far plane and off to the side
+        assert!(!frustum.intersects_aabb(&aabb([-0.5, -0.5, 1.0], [0.5, 0.5, 2.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-0.5, -0.5, -200.0], [0.5, 0.5, -150.0])));
+        assert!(!frustum.intersects_aabb(&aabb([-9.0, -0.5, -5.5], [-7.0, 0.5, -4.5])));
+        // Across the right, bottom, near and far planes
+        assert!(frustum.intersects_aabb(&aabb([4.5, -0.5, -5.5], [6.0, 0.5, -4.5])));
+        assert!(frustum.intersects_aabb(&aabb([-0.5, -6.0, -5.5], [0.5, -4.5, -4.5])));
+        assert!(frustum.intersects_aabb(&aabb([-0.5, -0.5, -1.0], [0.5, 0.5, 1.0])));
+        assert!(frustum.intersects_aabb(&aabb([-0.5, -0.5, -101.0], [0.5, 0.5, -99.0])));
+        // Bigger than the frustum
+        assert!(frustum.intersects_aabb(&aabb([-500.0; 3], [500.0; 3])));
+    }
+
+    #[test]
+    fn transformed_bounds() {
+        let unit = aabb([-1.0; 3], [1.0; 3]);
+        let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from(Quaternion::from_angle_y(Deg(45.0)))
+            * Matrix4::from_nonuniform_scale(1.0, 2.0, 1.0);
+
+        // The rotated box is wider along x and z, the scale stretches y
+        let aabb = unit.transform(&matrix);
+        let diagonal = 2.0_f32.sqrt();
+        assert_near(aabb.min, Point3::new(1.0 - diagonal, 0.0, 3.0 - diagonal));
+        assert_near(aabb.max, Point3::new(1.0 + diagonal, 4.0, 3.0 + diagonal));
+
+        let sphere = sphere([1.0, 0.0, 0.0], 1.0).transform(&matrix);
+        assert_near(
+            sphere.center,
+            Point3::new(1.0 + diagonal / 2.0, 2.0, 3.0 - diagonal / 2.0),
+        );
+        assert!((sphere.radius - 2.0).abs() < 1e-5);
+    }
+}
@@ -6,6 +6,8 @@ use cgmath::*;
 use instant::Duration;
 use std::f32::consts::FRAC_PI_2;
 
+use crate::bounds::Frustum;
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -81,6 +83,12 @@ pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
@@ -93,6 +101,11 @@ impl CameraUniform {
         self.view_position = camera.position.to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
     }
+
+    /// What the camera sees, from the last `update_view_proj`
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(&self.view_proj.into())
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,245 @@
+use std::{
+    future::Future,
+    io::Cursor,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use anyhow::{anyhow, bail};
+
+/// Pixels read back from a rendered frame
+pub struct Capture {
+    pub color: image::RgbaImage,
+    pub depth: Option<DepthImage>,
+}
+
+/// Raw depth values (0 = near plane, 1 = far plane), row by row
+pub struct DepthImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl DepthImage {
+    /// Depth as a 16 bit grayscale image, near is black and far is white
+    pub fn to_luma16(&self) -> image::ImageBuffer<image::Luma<u16>, Vec<u16>> {
+        let data = self
+            .data
+            .iter()
+            .map(|depth| (depth.clamp(0.0, 1.0) * u16::MAX as f32) as u16)
+            .collect();
+        image::ImageBuffer::from_raw(self.width, self.height, data)
+            .expect("Depth data doesn't match its size")
+    }
+}
+
+/// Copies a color texture (8 bit RGBA or BGRA) into an image
+/// The texture needs the `COPY_SRC` usage
+pub async fn read_color(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    format: wgpu::TextureFormat,
+    size: (u32, u32),
+) -> anyhow::Result<image::RgbaImage> {
+    use wgpu::TextureFormat::*;
+
+    let swap_red_blue = match format {
+        Rgba8Unorm | Rgba8UnormSrgb => false,
+        Bgra8Unorm | Bgra8UnormSrgb => true,
+        format => bail!("Can't capture texture format {:?}", format),
+    };
+
+    let mut pixels =
+        read_texture(device, queue, texture, wgpu::TextureAspect::All, size, 4).await?;
+    if swap_red_blue {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    image::RgbaImage::from_raw(size.0, size.1, pixels)
+        .ok_or_else(|| anyhow!("Captured data doesn't match the texture size"))
+}
+
+/// Copies a `Depth32Float` texture into a depth image
+/// The texture needs the `COPY_SRC` usage
+pub async fn read_depth(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    size: (u32, u32),
+) -> anyhow::Result<DepthImage> {
+    let bytes = read_texture(
+        device,
+        queue,
+        texture,
+        wgpu::TextureAspect::DepthOnly,
+        size,
+        4,
+    )
+    .await?;
+
+    Ok(DepthImage {
+        width: size.0,
+        height: size.1,
+        data: bytemuck::pod_collect_to_vec(&bytes),
+    })
+}
+
+// Copies the texture into a mappable buffer and strips the row padding
+async fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    aspect: wgpu::TextureAspect,
+    (width, height): (u32, u32),
+    bytes_per_pixel: u32,
+) -> anyhow::Result<Vec<u8>> {
+    // Buffer rows have to be aligned to 256 bytes
+    let unpadded_bytes_per_row = width * bytes_per_pixel;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Capture Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Capture Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    let mapped = MapFuture::default();
+    let state = mapped.state.clone();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let mut state = state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    // The browser maps the buffer on its own, natively we have to wait for the GPU
+    #[cfg(not(target_arch = "wasm32"))]
+    device.poll(wgpu::Maintain::Wait);
+    mapped.await?;
+
+    let pixels = {
+        let padded = slice.get_mapped_range();
+        padded
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+            .copied()
+            .collect::<Vec<_>>()
+    };
+    buffer.unmap();
+
+    Ok(pixels)
+}
+
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+// Resolves once `map_async` called back
+#[derive(Default)]
+struct MapFuture {
+    state: Arc<Mutex<MapState>>,
+}
+
+impl Future for MapFuture {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Encodes an image as PNG
+pub fn encode_png<P, C>(image: &image::ImageBuffer<P, C>) -> anyhow::Result<Vec<u8>>
+where
+    P: image::PixelWithColorType,
+    [P::Subpixel]: image::EncodableLayout,
+    C: std::ops::Deref<Target = [P::Subpixel]>,
+{
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), image::ImageOutputFormat::Png)?;
+    Ok(bytes)
+}
+
+/// Saves a PNG to disk, or downloads it from the browser on the web
+/// (only the file name of `path` is used there)
+pub fn save_png(png: &[u8], path: &Path) -> anyhow::Result<()> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        use wasm_bindgen::JsCast;
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "screenshot.png".to_string());
+
+        let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(png));
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type("image/png");
+        let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+            .map_err(|err| anyhow!("Couldn't create blob: {:?}", err))?;
+        let url = web_sys::Url::create_object_url_with_blob(&blob)
+            .map_err(|err| anyhow!("Couldn't create blob url: {:?}", err))?;
+
+        let document = web_sys::window()
+            .and_then(|win| win.document())
+            .ok_or_else(|| anyhow!("No document to download from"))?;
+        let anchor = document
+            .create_element("a")
+            .map_err(|err| anyhow!("Couldn't create link: {:?}", err))?
+            .dyn_into::<web_sys::HtmlAnchorElement>()
+            .map_err(|_| anyhow!("Couldn't create link"))?;
+        anchor.set_href(&url);
+        anchor.set_download(&file_name);
+        anchor.click();
+
+        web_sys::Url::revoke_object_url(&url)
+            .map_err(|err| anyhow!("Couldn't revoke blob url: {:?}", err))?;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    std::fs::write(path, png)?;
+
+    Ok(())
+}
@@ -1,14 +1,41 @@
-use crate::window::Window;
+use anyhow::Context;
+
+use crate::{post::PostChain, texture, window::Window};
 
 pub struct GraphicsContext {
     // Graphic context
-    pub surface: wgpu::Surface,
+    // No surface when rendering headless, frames go to `offscreen` instead
+    pub surface: Option<wgpu::Surface>,
+    // Kept around to query what the GPU supports
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
+    // Also describes the offscreen target (format + size) when headless
     pub config: wgpu::SurfaceConfiguration,
+    pub offscreen: Option<texture::Texture>,
+}
+
+/// The texture a frame is rendered into
+pub struct Frame {
+    surface_texture: Option<wgpu::SurfaceTexture>,
+    pub view: wgpu::TextureView,
+}
+
+impl Frame {
+    /// Shows the frame on screen (does nothing for offscreen frames)
+    pub fn present(self) {
+        if let Some(surface_texture) = self.surface_texture {
+            surface_texture.present();
+        }
+    }
 }
 
 impl GraphicsContext {
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    // Enabled when the adapter has them, see `DrawMode`
+    const OPTIONAL_FEATURES: wgpu::Features =
+        wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
     pub async fn new(window: &Window) -> GraphicsContext {
         let size = &window.window.inner_size();
 
@@ -30,7 +57,7 @@ impl GraphicsContext {
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features: adapter.features() & Self::OPTIONAL_FEATURES,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     limits: if cfg!(target_arch = "wasm32") {
@@ -57,10 +84,162 @@ impl GraphicsContext {
         surface.configure(&device, &config);
 
         GraphicsContext {
-            surface,
+            surface: Some(surface),
+            adapter,
             device,
             queue,
             config,
+            offscreen: None,
+        }
+    }
+
+    /// Creates a context without window, rendering into an offscreen texture
+    /// Falls back to a software adapter when no GPU is available
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<GraphicsContext> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.context("No graphics adapter found (not even a fallback one)")?;
+        log::info!("Headless adapter: {:?}", adapter.get_info());
+
+        // Software adapters usually can't reach the default limits
+        let limits = [wgpu::Limits::default(), wgpu::Limits::downlevel_defaults()]
+            .into_iter()
+            .find(|limits| limits.check_limits(&adapter.limits()))
+            .unwrap_or_else(|| {
+                wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+            });
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Headless device"),
+                    features: adapter.features() & Self::OPTIONAL_FEATURES,
+                    limits,
+                },
+                None,
+            )
+            .await?;
+
+        // Not used to configure any surface, it only describes the offscreen target
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: Self::OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+        };
+        let offscreen = texture::Texture::create_render_target(&device, &config, "Offscreen");
+
+        Ok(GraphicsContext {
+            surface: None,
+            adapter,
+            device,
+            queue,
+            config,
+            offscreen: Some(offscreen),
+        })
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.surface.is_none()
+    }
+
+    /// Applies a new size to the surface (or recreates the offscreen target)
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.config.width = width;
+        self.config.height = height;
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.config),
+            None => {
+                self.offscreen = Some(texture::Texture::create_render_target(
+                    &self.device,
+                    &self.config,
+                    "Offscreen",
+                ))
+            }
+        }
+    }
+
+    /// Whether the scene can be drawn with MSAA when `requested`
+    /// The HDR and depth formats must be multisampled (and the HDR one resolved)
+    pub fn supported_msaa(&self, requested: bool) -> bool {
+        if !requested {
+            return false;
+        }
+
+        let multisampled = [PostChain::HDR_FORMAT, texture::Texture::DEPTH_FORMAT]
+            .iter()
+            .all(|format| {
+                let flags = self.adapter.get_texture_format_features(*format).flags;
+                flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE)
+            });
+        let resolvable = self
+            .adapter
+            .get_texture_format_features(PostChain::HDR_FORMAT)
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+        if !multisampled || !resolvable {
+            log::warn!(
+                "The adapter can't multisample {:?}, MSAA is disabled",
+                PostChain::HDR_FORMAT
+            );
+        }
+        multisampled && resolvable
+    }
+
+    /// Whether the scene can be drawn with indirect draws when `requested`
+    /// WebGL can neither draw indirectly nor offset the vertices of indexed draws
+    pub fn supported_indirect_draws(&self, requested: bool) -> bool {
+        let flags = self.adapter.get_downlevel_capabilities().flags;
+        let supported = flags
+            .contains(wgpu::DownlevelFlags::INDIRECT_EXECUTION | wgpu::DownlevelFlags::BASE_VERTEX);
+        if requested && !supported {
+            log::warn!("The adapter can't draw indirectly, meshes are drawn one by one");
+        }
+        requested && supported
+    }
+
+    /// Whether the device can run the compute shaders (with two storage buffers)
+    pub fn supports_compute_shaders(&self) -> bool {
+        let flags = self.adapter.get_downlevel_capabilities().flags;
+        flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            && self.device.limits().max_storage_buffers_per_shader_stage >= 2
+    }
+
+    /// Gets the texture to render the next frame into
+    pub fn acquire_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        match (&self.surface, &self.offscreen) {
+            (Some(surface), _) => {
+                let surface_texture = surface.get_current_texture()?;
+                let view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(Frame {
+                    surface_texture: Some(surface_texture),
+                    view,
+                })
+            }
+            (None, Some(offscreen)) => Ok(Frame {
+                surface_texture: None,
+                view: offscreen
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            }),
+            (None, None) => Err(wgpu::SurfaceError::Lost),
         }
     }
 }
@@ -0,0 +1,302 @@
+use std::num::NonZeroU32;
+
+use anyhow::ensure;
+use image::GenericImageView;
+use wgpu::{util::DeviceExt, Device, Queue};
+
+use crate::{post::PostChain, texture};
+
+/// Cubemap from six square images of the same size, in the order +X, -X, +Y, -Y, +Z, -Z
+pub fn from_faces(
+    device: &Device,
+    queue: &Queue,
+    faces: &[image::DynamicImage],
+    label: &str,
+) -> anyhow::Result<texture::Texture> {
+    ensure!(
+        faces.len() == 6,
+        "A cubemap has 6 faces, not {}",
+        faces.len()
+    );
+    let (size, height) = faces[0].dimensions();
+    ensure!(
+        size == height,
+        "The faces of a cubemap must be square, not {}x{}",
+        size,
+        height
+    );
+    ensure!(
+        faces.iter().all(|face| face.dimensions() == (size, size)),
+        "The faces of a cubemap must all be the same size"
+    );
+
+    let cubemap = texture::Texture::create_cube(
+        device,
+        size,
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        1,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label,
+    );
+    for (layer, face) in faces.iter().enumerate() {
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &cubemap.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer as u32,
+                },
+            },
+            &face.to_rgba8(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * size),
+                rows_per_image: NonZeroU32::new(size),
+            },
+            wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    Ok(cubemap)
+}
+
+/// Cubemap from an equirectangular (latitude/longitude) image, projected on the GPU
+/// The faces are a quarter of the image wide and keep its HDR colors
+pub fn from_equirectangular(
+    device: &Device,
+    queue: &Queue,
+    image: &image::DynamicImage,
+    label: &str,
+) -> texture::Texture {
+    let (width, height) = image.dimensions();
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let equirectangular = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &equirectangular,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&image.to_rgba32f()),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(16 * width),
+            rows_per_image: NonZeroU32::new(height),
+        },
+        size,
+    );
+
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("[Cubemap] Equirectangular layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        }],
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("[Cubemap] Equirectangular bind group"),
+        layout: &layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(
+                &equirectangular.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        }],
+    });
+
+    let faces = FaceRenderer::new(device);
+    let pipeline = faces.pipeline(
+        device,
+        "[Cubemap] Equirectangular pipeline",
+        include_str!("shaders/equirectangular.wgsl"),
+        "fs_main",
+        &layout,
+        PostChain::HDR_FORMAT,
+    );
+    let cubemap = texture::Texture::create_cube(
+        device,
+        (width / 4).max(1),
+        PostChain::HDR_FORMAT,
+        1,
+        wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        label,
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("[Cubemap] Encoder"),
+    });
+    faces.render(&mut encoder, &pipeline, &bind_group, &cubemap.texture, 0);
+    queue.submit(Some(encoder.finish()));
+
+    cubemap
+}
+
+/// Draws a full-screen triangle into each face of a cubemap, the fragment shaders get
+/// the direction of their pixel from `face_direction` (see `shaders/cubemap.wgsl`)
+pub(crate) struct FaceRenderer {
+    layout: wgpu::BindGroupLayout,
+    // The face index of each face, bound to group 1
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl FaceRenderer {
+    pub fn new(device: &Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("[Cubemap] Face layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_groups = (0..6u32)
+            .map(|index| {
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("[Cubemap] Face"),
+                    contents: bytemuck::cast_slice(&[index, 0, 0, 0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("[Cubemap] Face bind group"),
+                    layout: &layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                })
+            })
+            .collect();
+
+        Self {
+            layout,
+            bind_groups,
+        }
+    }
+
+    /// A pipeline running the `entry_point` of `source` (after `shaders/cubemap.wgsl`),
+    /// with `layout` as group 0
+    pub fn pipeline(
+        &self,
+        device: &Device,
+        label: &str,
+        source: &str,
+        entry_point: &str,
+        layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(
+                format!("{}\n{}", include_str!("shaders/cubemap.wgsl"), source).into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[layout, &self.layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: Default::default(),
+            depth_stencil: None,
+            multisample: Default::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    }
+
+    /// Renders the six faces of `cubemap` at `mip_level`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        cubemap: &wgpu::Texture,
+        mip_level: u32,
+    ) {
+        for layer in 0..self.bind_groups.len() {
+            let view = cubemap.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("[Cubemap] Face"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: NonZeroU32::new(1),
+                base_array_layer: layer as u32,
+                array_layer_count: NonZeroU32::new(1),
+                ..Default::default()
+            });
+            self.draw(encoder, pipeline, bind_group, &view, layer);
+        }
+    }
+
+    /// Renders a single face into `target`, which can also be a plain 2D texture
+    pub fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+        target: &wgpu::TextureView,
+        face: usize,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("[Cubemap] Face pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_groups[face], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,602 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Context;
+use winit::{dpi::PhysicalPosition, event::*};
+
+use crate::{
+    camera::{self, Camera, CameraController},
+    capture::{self, Capture},
+    context::GraphicsContext,
+    graph::{self, RenderGraph, TexturePool},
+    instant::Instant,
+    light::Light,
+    model::Keyframes,
+    node::Nodes,
+    particle::ParticleSimulation,
+    pass::{culling::CullingStats, phong::PhongConfig, scene::ScenePass, Pass},
+    post::PostChain,
+    scene::{
+        LightDescriptor, ModelSource, NodeDescriptor, ParticleSystemDescriptor, Scene, SkyboxSource,
+    },
+    scene_file::SceneFile,
+    texture,
+    window::{Window, WindowEvents},
+};
+
+/// Called once per frame, before the scene is uploaded to the GPU
+pub type UpdateCallback = Box<dyn FnMut(&mut Scene, Duration)>;
+
+/// Builds an `Engine` from a description of the scene
+///
+/// Nothing touches the GPU until `build` (or `run`) is called,
+/// so models are described with a `ModelSource` and loaded then.
+pub struct EngineBuilder {
+    title: String,
+    camera: Camera,
+    camera_speed: f32,
+    camera_sensitivity: f32,
+    phong_config: PhongConfig,
+    // None until a light is added, the scene then gets a default white light
+    lights: Option<Vec<LightDescriptor>>,
+    light_model: Option<ModelSource>,
+    // None keeps the clear color behind the scene
+    skybox: Option<SkyboxSource>,
+    nodes: Vec<NodeDescriptor>,
+    particle_systems: Vec<ParticleSystemDescriptor>,
+    update_callbacks: Vec<UpdateCallback>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            title: "MJOLNIR".to_string(),
+            camera: Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0)),
+            camera_speed: 4.0,
+            camera_sensitivity: 0.4,
+            phong_config: PhongConfig::default(),
+            lights: None,
+            light_model: Some(ModelSource::Sphere {
+                radius: 0.5,
+                sectors: 36,
+                stacks: 18,
+            }),
+            skybox: None,
+            nodes: Vec::new(),
+            particle_systems: Vec::new(),
+            update_callbacks: Vec::new(),
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_camera(mut self, camera: Camera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_camera_controller(mut self, speed: f32, sensitivity: f32) -> Self {
+        self.camera_speed = speed;
+        self.camera_sensitivity = sensitivity;
+        self
+    }
+
+    pub fn with_phong_config(mut self, phong_config: PhongConfig) -> Self {
+        self.phong_config = phong_config;
+        self
+    }
+
+    /// Adds a light, the default one is only used when no light is added
+    pub fn with_light(self, light: Light) -> Self {
+        self.with_light_descriptor(LightDescriptor {
+            light,
+            parent: None,
+        })
+    }
+
+    /// Adds a light attached to a node (index in the order nodes were added)
+    pub fn with_attached_light(self, light: Light, parent: usize) -> Self {
+        self.with_light_descriptor(LightDescriptor {
+            light,
+            parent: Some(parent),
+        })
+    }
+
+    pub fn with_light_descriptor(mut self, light: LightDescriptor) -> Self {
+        self.lights.get_or_insert_with(Vec::new).push(light);
+        self
+    }
+
+    /// Model drawn at each light position, `None` hides the lights
+    pub fn with_light_model(mut self, light_model: Option<ModelSource>) -> Self {
+        self.light_model = light_model;
+        self
+    }
+
+    /// Cubemap drawn behind the scene, `None` clears it to a flat color
+    pub fn with_skybox(mut self, skybox: Option<SkyboxSource>) -> Self {
+        self.skybox = skybox;
+        self
+    }
+
+    pub fn with_node(mut self, node: NodeDescriptor) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Adds the nodes of a scene file after the ones already added
+    /// Its lights are added too, its camera, Phong options and skybox (when set) replace the current ones
+    pub fn with_scene_file(mut self, scene: SceneFile) -> Self {
+        let offset = self.nodes.len();
+
+        if let Some(camera) = scene.camera {
+            self.camera = camera;
+        }
+        if let Some(phong_config) = scene.phong_config {
+            self.phong_config = phong_config;
+        }
+        if let Some(skybox) = scene.skybox {
+            self.skybox = Some(skybox);
+        }
+        for mut light in scene.lights {
+            light.parent = light.parent.map(|parent| parent + offset);
+            self = self.with_light_descriptor(light);
+        }
+
+        self.nodes.extend(scene.nodes.into_iter().map(|mut node| {
+            node.parent = node.parent.map(|parent| parent + offset);
+            node
+        }));
+        self
+    }
+
+    pub fn with_particle_system(mut self, particle_system: ParticleSystemDescriptor) -> Self {
+        self.particle_systems.push(particle_system);
+        self
+    }
+
+    /// Registers a callback run every frame with the time elapsed since the last one
+    pub fn on_update(mut self, callback: impl FnMut(&mut Scene, Duration) + 'static) -> Self {
+        self.update_callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Initializes the GPU for `window` and loads every model of the scene
+    pub async fn build(self, window: &Window) -> anyhow::Result<Engine> {
+        let ctx = GraphicsContext::new(window).await;
+        self.build_with_context(ctx).await
+    }
+
+    /// Same as `build` but renders into an offscreen texture of the given size,
+    /// no window or display needed
+    pub async fn build_headless(self, width: u32, height: u32) -> anyhow::Result<Engine> {
+        let ctx = GraphicsContext::new_headless(width, height).await?;
+        self.build_with_context(ctx).await
+    }
+
+    async fn build_with_context(self, ctx: GraphicsContext) -> anyhow::Result<Engine> {
+        let size = winit::dpi::PhysicalSize::new(ctx.config.width, ctx.config.height);
+
+        let light_model = match &self.light_model {
+            Some(source) => Some(source.load(&ctx.device, &ctx.queue).await?),
+            None => None,
+        };
+        let skybox = match &self.skybox {
+            Some(source) => Some(source.load(&ctx.device, &ctx.queue).await?),
+            None => None,
+        };
+
+        let phong_config = PhongConfig {
+            msaa: ctx.supported_msaa(self.phong_config.msaa),
+            indirect_draws: ctx.supported_indirect_draws(self.phong_config.indirect_draws),
+            ..self.phong_config.clone()
+        };
+        let pass = Box::new(ScenePass::new(
+            &phong_config,
+            phong_config.shading.shader(),
+            &ctx.device,
+            &ctx.queue,
+            &ctx.config,
+            &self.camera,
+            light_model,
+            skybox,
+        ));
+
+        // Load 3D model from disk or as a HTTP request (for web support)
+        let mut nodes = Nodes::new();
+        let mut ids = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            ids.push(nodes.insert(node.build(&ctx.device, &ctx.queue).await?));
+        }
+        // Parents are given as indices in the order nodes were added
+        let resolve = |parent: usize| {
+            ids.get(parent)
+                .copied()
+                .with_context(|| format!("There is no node #{} to attach to", parent))
+        };
+        for (descriptor, id) in self.nodes.iter().zip(&ids) {
+            if let Some(parent) = descriptor.parent {
+                nodes[*id].set_parent(Some(resolve(parent)?));
+            }
+        }
+
+        let lights = self.lights.unwrap_or_else(|| {
+            vec![LightDescriptor {
+                light: Light::new([2.0, 2.0, 2.0], [1.0, 1.0, 1.0]).with_intensity(10.0),
+                parent: None,
+            }]
+        });
+        if lights.len() > pass.max_lights() {
+            log::warn!(
+                "The scene has {} lights but only {} are rendered (see PhongConfig::max_lights)",
+                lights.len(),
+                pass.max_lights()
+            );
+        }
+        let lights = lights
+            .iter()
+            .map(|light| {
+                Ok(Light {
+                    parent: light.parent.map(resolve).transpose()?,
+                    ..light.light
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let particle_simulation = ctx
+            .supports_compute_shaders()
+            .then(|| ParticleSimulation::new(&ctx.device));
+        let mut particle_systems = Vec::with_capacity(self.particle_systems.len());
+        for descriptor in &self.particle_systems {
+            let mut particle_system = descriptor
+                .build(&ctx.device, &ctx.queue, particle_simulation.as_ref())
+                .await?;
+            particle_system.parent = descriptor.parent.map(resolve).transpose()?;
+            particle_systems.push(particle_system);
+        }
+
+        let camera_controller =
+            camera::CameraController::new(self.camera_speed, self.camera_sensitivity);
+
+        Ok(Engine {
+            post: PostChain::new(&ctx.device, &ctx.config, &phong_config.post_effects),
+            textures: TexturePool::new(ctx.config.width, ctx.config.height),
+            ctx,
+            pass,
+            size,
+            camera_controller,
+            scene: Scene {
+                camera: self.camera,
+                lights,
+                nodes,
+                particle_systems,
+            },
+            particle_simulation,
+            update_callbacks: self.update_callbacks,
+            time: Duration::ZERO,
+        })
+    }
+
+    /// Opens a window and runs the engine until it is closed
+    pub async fn run(self) -> anyhow::Result<()> {
+        let window = Window::new(&self.title);
+
+        // Engine::build uses async code, so we're going to wait for it to finish
+        let mut engine = self.build(&window).await?;
+        let mut last_render_time = Instant::now();
+
+        window.run(move |event| match event {
+            WindowEvents::Resized { width, height } => {
+                engine.resize(winit::dpi::PhysicalSize { width, height });
+            }
+            WindowEvents::Draw => {
+                let dt = last_render_time.elapsed();
+                last_render_time = Instant::now();
+
+                engine.update(dt);
+                if let Err(err) = engine.render() {
+                    log::error!("Error in rendering {:?}", err);
+                }
+            }
+            WindowEvents::Keyboard {
+                state,
+                virtual_keycode,
+            } => {
+                engine.keyboard(state, virtual_keycode);
+            }
+
+            WindowEvents::MouseWheel { delta } => {
+                engine.scroll(delta);
+            }
+
+            WindowEvents::MouseMoved { position } => {
+                engine.mouse_moved(*position);
+            }
+
+            WindowEvents::MouseInput { state, button } => {
+                engine.mouse_input(state, button);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+pub struct Engine {
+    ctx: GraphicsContext,
+    // Draws with the shader of `PhongConfig::shading` (Phong or PBR)
+    pass: Box<dyn Pass>,
+    // Turns the HDR scene drawn by the pass into the frame
+    post: PostChain,
+    // Attachments of the render graph, kept between frames
+    textures: TexturePool,
+    // Window size
+    size: winit::dpi::PhysicalSize<u32>,
+    camera_controller: CameraController,
+    scene: Scene,
+    // Moves the particles on the GPU, None without compute shaders (WebGL)
+    particle_simulation: Option<ParticleSimulation>,
+    update_callbacks: Vec<UpdateCallback>,
+    // Animation time, the sum of every `dt` so far
+    // (not wall clock, so headless renders are reproducible)
+    time: Duration,
+}
+
+impl Engine {
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    pub fn context(&self) -> &GraphicsContext {
+        &self.ctx
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    // Keeps state in sync with window size when changed
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = new_size;
+            self.ctx.resize(new_size.width, new_size.height);
+
+            self.pass.resize(&self.ctx.config);
+            self.textures.resize(new_size.width, new_size.height);
+        }
+    }
+
+    // Handle input using WindowEvent
+    pub(crate) fn keyboard(&mut self, state: ElementState, keycode: &VirtualKeyCode) -> bool {
+        // Send any input to camera controller
+        self.camera_controller.process_keyboard(*keycode, state)
+    }
+
+    pub(crate) fn mouse_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.camera_controller.process_mouse(position);
+    }
+
+    pub(crate) fn mouse_input(&mut self, state: &ElementState, button: &MouseButton) {
+        self.camera_controller.process_mouse_input(state, button);
+    }
+
+    pub(crate) fn scroll(&mut self, delta: &MouseScrollDelta) {
+        self.camera_controller.process_scroll(delta);
+    }
+
+    /// Time since the engine started, as accumulated by `update`
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// How many meshes the camera culled during the last `update` (in `Pass::update_nodes`),
+    /// which is what the next rendered frame draws
+    pub fn culling_stats(&self) -> CullingStats {
+        self.pass.culling_stats()
+    }
+
+    /// Runs the update callbacks then uploads the scene to the GPU
+    pub fn update(&mut self, dt: Duration) {
+        self.time += dt;
+
+        for callback in &mut self.update_callbacks {
+            callback(&mut self.scene, dt);
+        }
+
+        // Sync local app state with camera
+        self.camera_controller
+            .update_camera(&mut self.scene.camera, dt);
+        self.pass.update_camera(&self.scene.camera, &self.ctx.queue);
+
+        #[cfg(debug_assertions)]
+        log::debug!("Time elapsed: {:?}", &self.time);
+
+        // Animations drive the local transform of the nodes
+        let current_time = &self.time.as_secs_f32();
+        for (_, node) in self.scene.nodes.iter_mut() {
+            if !node.model.animations.is_empty() {
+                // Loop through all animations
+                // TODO: Ideally we'd play a certain animation by name - we assume first one for now
+                let mut current_keyframe_index = 0;
+                for animation in &node.model.animations {
+                    for timestamp in &animation.timestamps {
+                        if timestamp > current_time {
+                            break;
+                        }
+                        if current_keyframe_index < &animation.timestamps.len() - 1 {
+                            current_keyframe_index += 1;
+                        }
+                    }
+                }
+
+                // Update the node transform with current animation
+                let current_animation = &node.model.animations[0].keyframes;
+                let mut current_frame: Option<&Vec<f32>> = None;
+                match current_animation {
+                    Keyframes::Translation(frames) => {
+                        current_frame = Some(&frames[current_keyframe_index])
+                    }
+                    Keyframes::Other => (),
+                }
+
+                if let Some(current_frame) = current_frame {
+                    node.transform_mut().position =
+                        cgmath::Vector3::new(current_frame[0], current_frame[1], current_frame[2]);
+                }
+            }
+        }
+
+        // Propagate the transforms through the scene graph
+        let changed = self.scene.update_transforms();
+        // Nodes inserted since the last update get their GPU resources,
+        // the ones of removed nodes are freed, and only the nodes that actually moved
+        // are uploaded (new nodes always count as moved)
+        self.pass.update_nodes(
+            &mut self.scene.nodes,
+            &changed,
+            &self.ctx.device,
+            &self.ctx.queue,
+        );
+
+        // Emitters follow their node, so after the transforms
+        for particle_system in &mut self.scene.particle_systems {
+            particle_system.update(
+                dt,
+                &self.scene.nodes,
+                &self.ctx.device,
+                &self.ctx.queue,
+                self.particle_simulation.as_ref(),
+            );
+        }
+
+        // Update the lights (after the transforms, they may follow a node)
+        self.pass
+            .set_lights(&self.scene.light_uniforms(), &self.ctx.queue);
+    }
+
+    /// Renders the scene and reads the frame back from the GPU
+    /// `with_depth` also reads the depth buffer, if the adapter can copy depth textures
+    pub async fn capture(&mut self, with_depth: bool) -> anyhow::Result<Capture> {
+        let size = (self.ctx.config.width, self.ctx.config.height);
+
+        // The window surface can't be copied from,
+        // so the frame is rendered again into a texture we own
+        let window_target = match self.ctx.offscreen {
+            Some(_) => None,
+            None => Some(texture::Texture::create_render_target(
+                &self.ctx.device,
+                &self.ctx.config,
+                "Capture",
+            )),
+        };
+        let target = window_target
+            .as_ref()
+            .or(self.ctx.offscreen.as_ref())
+            .expect("Either a window or an offscreen target");
+
+        let mut graph = frame_graph(self.pass.as_ref(), &self.post, &self.scene);
+        // The depth attachment is only kept around when it is read back
+        if with_depth {
+            graph.export(graph::DEPTH);
+        }
+        graph.execute(
+            &self.ctx.device,
+            &self.ctx.queue,
+            &mut self.textures,
+            &target.view,
+        );
+
+        let color = capture::read_color(
+            &self.ctx.device,
+            &self.ctx.queue,
+            &target.texture,
+            self.ctx.config.format,
+            size,
+        )
+        .await?;
+
+        let depth = if with_depth {
+            let copies_supported = self
+                .ctx
+                .adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::DEPTH_TEXTURE_AND_BUFFER_COPIES);
+            anyhow::ensure!(
+                copies_supported,
+                "This adapter can't copy depth textures to buffers"
+            );
+            // Multisampled textures can't be copied either
+            anyhow::ensure!(
+                self.pass.sample_count() == 1,
+                "The depth buffer can't be read with MSAA enabled"
+            );
+
+            Some(
+                capture::read_depth(
+                    &self.ctx.device,
+                    &self.ctx.queue,
+                    &self
+                        .textures
+                        .get(graph::DEPTH)
+                        .expect("The depth attachment is exported")
+                        .texture,
+                    size,
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Capture { color, depth })
+    }
+
+    /// Captures the frame as a PNG, written to `path` (or downloaded on the web)
+    pub async fn save_screenshot(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let capture = self.capture(false).await?;
+        let png = capture::encode_png(&capture.color)?;
+        capture::save_png(&png, path.as_ref())
+    }
+
+    // Primary render flow
+    // Draws into the window surface, or the offscreen texture when headless
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let frame = self.ctx.acquire_frame()?;
+
+        frame_graph(self.pass.as_ref(), &self.post, &self.scene).execute(
+            &self.ctx.device,
+            &self.ctx.queue,
+            &mut self.textures,
+            &frame.view,
+        );
+
+        frame.present();
+
+        Ok(())
+    }
+}
+
+// The nodes of a frame: the pass draws the scene, the post chain turns it into the frame
+fn frame_graph<'a>(pass: &'a dyn Pass, post: &'a PostChain, scene: &'a Scene) -> RenderGraph<'a> {
+    let mut graph = RenderGraph::new();
+    pass.add_to_graph(&mut graph, &scene.nodes, &scene.particle_systems);
+    post.add_to_graph(&mut graph);
+    graph
+}
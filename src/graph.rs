@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use wgpu::{CommandEncoder, Device, Queue, TextureView};
+
+use crate::texture;
+
+/// The frame being rendered (window surface or capture target), given to `RenderGraph::execute`
+pub const FRAME: &str = "frame";
+/// HDR color of the scene, drawn by the pass then post-processed into the frame
+pub const SCENE: &str = "scene";
+/// Depth of the scene
+pub const DEPTH: &str = "depth";
+
+/// A texture the graph allocates for the nodes of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttachmentDesc {
+    pub format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    // The frame size is divided by it (2 for half resolution)
+    pub downscale: u32,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl AttachmentDesc {
+    /// A frame sized texture, rendered to then sampled by the next nodes
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            sample_count: 1,
+            downscale: 1,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        }
+    }
+
+    pub fn with_sample_count(self, sample_count: u32) -> Self {
+        Self {
+            sample_count,
+            ..self
+        }
+    }
+
+    pub fn with_downscale(self, downscale: u32) -> Self {
+        Self { downscale, ..self }
+    }
+
+    pub fn with_usage(self, usage: wgpu::TextureUsages) -> Self {
+        Self { usage, ..self }
+    }
+
+    fn size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let downscale = self.downscale.max(1);
+        ((width / downscale).max(1), (height / downscale).max(1))
+    }
+}
+
+/// What a node records its commands with
+pub struct RenderContext<'r> {
+    pub encoder: &'r mut CommandEncoder,
+    pub device: &'r Device,
+    pub queue: &'r Queue,
+    textures: &'r TexturePool,
+    frame: &'r TextureView,
+}
+
+impl<'r> RenderContext<'r> {
+    /// The view of an attachment the node reads or writes (or of `FRAME`)
+    pub fn view(&self, name: &str) -> &'r TextureView {
+        if name == FRAME {
+            return self.frame;
+        }
+        &self.texture(name).view
+    }
+
+    pub fn texture(&self, name: &str) -> &'r texture::Texture {
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("`{}` isn't an attachment of the node", name))
+    }
+}
+
+type Record<'a> = Box<dyn FnOnce(&mut RenderContext) + 'a>;
+
+struct GraphNode<'a> {
+    name: String,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    record: Record<'a>,
+}
+
+/// The passes of a frame and the textures they share
+/// Nodes are ordered by what they read and write (each resource has a single writer),
+/// the ones whose output nobody uses are skipped. Attachments are allocated from a
+/// `TexturePool` when first written, and given back after their last use so later
+/// attachments of the frame can reuse them.
+/// Everything is recorded in a single command encoder and submitted at once
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    attachments: HashMap<String, AttachmentDesc>,
+    // Attachments still readable from the pool after the frame
+    exports: Vec<String>,
+    nodes: Vec<GraphNode<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a texture of the frame, allocated by the graph
+    pub fn add_attachment(&mut self, name: impl Into<String>, desc: AttachmentDesc) {
+        self.attachments.insert(name.into(), desc);
+    }
+
+    /// Keeps an attachment around after the frame (see `TexturePool::get`)
+    pub fn export(&mut self, name: impl Into<String>) {
+        self.exports.push(name.into());
+    }
+
+    /// Adds a node recording its commands with `record`
+    /// Names that are neither attachments nor `FRAME` are resources the nodes own themselves
+    /// (like the shadow maps), they only order the nodes
+    pub fn add_node(
+        &mut self,
+        name: impl Into<String>,
+        reads: &[&str],
+        writes: &[&str],
+        record: impl FnOnce(&mut RenderContext) + 'a,
+    ) {
+        let to_strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        self.nodes.push(GraphNode {
+            name: name.into(),
+            reads: to_strings(reads),
+            writes: to_strings(writes),
+            record: Box::new(record),
+        });
+    }
+
+    // Indices of the nodes to run, writers before their readers
+    // (in the order they were added when nothing else decides)
+    fn order(&self) -> Vec<usize> {
+        let mut writers = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            for name in &node.writes {
+                if let Some(other) = writers.insert(name.as_str(), index) {
+                    panic!(
+                        "`{}` is written by both `{}` and `{}`",
+                        name, self.nodes[other].name, node.name
+                    );
+                }
+            }
+        }
+
+        let dependencies = self
+            .nodes
+            .iter()
+            .map(|node| {
+                node.reads
+                    .iter()
+                    .filter_map(|name| {
+                        let writer = writers.get(name.as_str()).copied();
+                        if writer.is_none() && self.attachments.contains_key(name) {
+                            panic!("`{}` reads `{}` but no node writes it", node.name, name);
+                        }
+                        writer
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut placed = vec![false; self.nodes.len()];
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len())
+                .find(|&index| {
+                    !placed[index] && dependencies[index].iter().all(|&other| placed[other])
+                })
+                .unwrap_or_else(|| panic!("The render graph has a cycle"));
+            placed[next] = true;
+            order.push(next);
+        }
+
+        // Walking back from the frame, only the nodes something needs are kept
+        let mut needed = self
+            .exports
+            .iter()
+            .map(String::as_str)
+            .chain([FRAME])
+            .collect::<Vec<_>>();
+        let mut kept = Vec::with_capacity(order.len());
+        for &index in order.iter().rev() {
+            let node = &self.nodes[index];
+            if node
+                .writes
+                .iter()
+                .any(|name| needed.contains(&name.as_str()))
+            {
+                needed.extend(node.reads.iter().map(String::as_str));
+                kept.push(index);
+            } else {
+                #[cfg(debug_assertions)]
+                log::debug!("Skipping `{}`, nothing uses what it writes", node.name);
+            }
+        }
+        kept.reverse();
+        kept
+    }
+
+    /// Records the nodes and submits them, `frame` is what `FRAME` refers to
+    pub fn execute(
+        self,
+        device: &Device,
+        queue: &Queue,
+        textures: &mut TexturePool,
+        frame: &TextureView,
+    ) {
+        let order = self.order();
+
+        // Attachments are given back to the pool after the last node using them
+        let mut last_use = HashMap::new();
+        for (position, &index) in order.iter().enumerate() {
+            let node = &self.nodes[index];
+            for name in node.reads.iter().chain(&node.writes) {
+                if self.attachments.contains_key(name) && !self.exports.contains(name) {
+                    last_use.insert(name.clone(), position);
+                }
+            }
+        }
+
+        textures.begin_frame();
+        let mut nodes = self.nodes.into_iter().map(Some).collect::<Vec<_>>();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Graph Encoder"),
+        });
+        for (position, index) in order.into_iter().enumerate() {
+            let node = nodes[index].take().expect("Nodes run once");
+            for name in &node.writes {
+                if let Some(desc) = self.attachments.get(name) {
+                    textures.acquire(device, name, desc);
+                }
+            }
+
+            let mut ctx = RenderContext {
+                encoder: &mut encoder,
+                device,
+                queue,
+                textures,
+                frame,
+            };
+            (node.record)(&mut ctx);
+
+            for name in node.reads.iter().chain(&node.writes) {
+                if last_use.get(name) == Some(&position) {
+                    textures.release(name);
+                }
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+struct PooledTexture {
+    desc: AttachmentDesc,
+    texture: texture::Texture,
+    in_use: bool,
+}
+
+/// The textures behind the attachments of the graph, kept from one frame to the next
+/// and shared by attachments that are never used at the same time
+pub struct TexturePool {
+    size: (u32, u32),
+    textures: Vec<PooledTexture>,
+    // Texture of each attachment of the frame
+    assigned: HashMap<String, usize>,
+}
+
+impl TexturePool {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            size: (width, height),
+            textures: Vec::new(),
+            assigned: HashMap::new(),
+        }
+    }
+
+    /// The textures of the previous size are dropped, the next frame allocates new ones
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+        self.textures.clear();
+        self.assigned.clear();
+    }
+
+    /// The texture of an attachment, during the frame or after it for the exported ones
+    pub fn get(&self, name: &str) -> Option<&texture::Texture> {
+        self.assigned
+            .get(name)
+            .map(|&index| &self.textures[index].texture)
+    }
+
+    // Exports of the previous frame are given back
+    fn begin_frame(&mut self) {
+        self.assigned.clear();
+        for pooled in &mut self.textures {
+            pooled.in_use = false;
+        }
+    }
+
+    fn acquire(&mut self, device: &Device, name: &str, desc: &AttachmentDesc) {
+        let free = self
+            .textures
+            .iter()
+            .position(|pooled| !pooled.in_use && pooled.desc == *desc);
+        let index = free.unwrap_or_else(|| {
+            #[cfg(debug_assertions)]
+            log::debug!("Allocating a texture for `{}`", name);
+
+            let (width, height) = desc.size(self.size);
+            self.textures.push(PooledTexture {
+                desc: *desc,
+                texture: texture::Texture::create_attachment(
+                    device,
+                    (width, height),
+                    desc.format,
+                    desc.sample_count,
+                    desc.usage,
+                    name,
+                ),
+                in_use: false,
+            });
+            self.textures.len() - 1
+        });
+        self.textures[index].in_use = true;
+        self.assigned.insert(name.to_string(), index);
+    }
+
+    fn release(&mut self, name: &str) {
+        if let Some(index) = self.assigned.remove(name) {
+            self.textures[index].in_use = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(graph: &mut RenderGraph, name: &str, reads: &[&str], writes: &[&str]) {
+        graph.add_node(name, reads, writes, |_| {});
+    }
+
+    fn order<'g>(graph: &'g RenderGraph) -> Vec<&'g str> {
+        graph
+            .order()
+            .into_iter()
+            .map(|index| graph.nodes[index].name.as_str())
+            .collect()
+    }
+
+    fn attachment() -> AttachmentDesc {
+        AttachmentDesc::new(wgpu::TextureFormat::Rgba16Float)
+    }
+
+    #[test]
+    fn writers_first() {
+        let mut graph = RenderGraph::new();
+        graph.add_attachment(SCENE, attachment());
+        graph.add_attachment("bloom", attachment());
+        node(&mut graph, "tone mapping", &[SCENE, "bloom"], &[FRAME]);
+        node(&mut graph, "bloom", &[SCENE], &["bloom"]);
+        node(&mut graph, "scene", &["shadow maps"], &[SCENE]);
+        node(&mut graph, "shadows", &[], &["shadow maps"]);
+
+        assert_eq!(order(&graph), ["shadows", "scene", "bloom", "tone mapping"]);
+    }
+
+    #[test]
+    fn independent_nodes_keep_their_order() {
+        let mut graph = RenderGraph::new();
+        node(&mut graph, "b", &[], &["b"]);
+        node(&mut graph, "a", &[], &["a"]);
+        node(&mut graph, "frame", &["a", "b"], &[FRAME]);
+
+        assert_eq!(order(&graph), ["b", "a", "frame"]);
+    }
+
+    #[test]
+    fn unused_nodes_are_pruned() {
+        let mut graph = RenderGraph::new();
+        graph.add_attachment(SCENE, attachment());
+        graph.add_attachment("debug", attachment());
+        graph.add_attachment("depth copy", attachment());
+        graph.export("depth copy");
+        node(&mut graph, "scene", &[], &[SCENE]);
+        node(&mut graph, "debug", &[SCENE], &["debug"]);
+        node(&mut graph, "depth copy", &[], &["depth copy"]);
+        node(&mut graph, "present", &[SCENE], &[FRAME]);
+
+        // Exported attachments count as used
+        assert_eq!(order(&graph), ["scene", "depth copy", "present"]);
+    }
+
+    #[test]
+    fn nothing_reaches_the_frame() {
+        let mut graph = RenderGraph::new();
+        node(&mut graph, "a", &[], &["a"]);
+        node(&mut graph, "b", &["a"], &["b"]);
+
+        assert!(order(&graph).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "The render graph has a cycle")]
+    fn cycle() {
+        let mut graph = RenderGraph::new();
+        node(&mut graph, "a", &["b"], &["a"]);
+        node(&mut graph, "b", &["a"], &["b"]);
+        node(&mut graph, "frame", &["a"], &[FRAME]);
+        graph.order();
+    }
+
+    #[test]
+    #[should_panic(expected = "`scene` is written by both `a` and `b`")]
+    fn double_writer() {
+        let mut graph = RenderGraph::new();
+        node(&mut graph, "a", &[], &[SCENE]);
+        node(&mut graph, "b", &[], &[SCENE]);
+        graph.order();
+    }
+
+    #[test]
+    #[should_panic(expected = "`present` reads `scene` but no node writes it")]
+    fn missing_writer() {
+        let mut graph = RenderGraph::new();
+        graph.add_attachment(SCENE, attachment());
+        node(&mut graph, "present", &[SCENE], &[FRAME]);
+        graph.order();
+    }
+}
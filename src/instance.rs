@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use wgpu::VertexBufferLayout;
 
 // Instances
@@ -10,16 +12,19 @@ pub struct Instance {
 }
 
 impl Instance {
-    pub fn to_raw(&self) -> InstanceRaw {
+    /// From the instance to its node's space
+    pub fn model_matrix(&self) -> cgmath::Matrix4<f32> {
         let translation = cgmath::Matrix4::from_translation(self.position);
         let rotation = cgmath::Matrix4::from(self.rotation);
         let scale =
             cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
 
-        let model = (translation * rotation) * scale;
+        (translation * rotation) * scale
+    }
 
+    pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            model: model.into(),
+            model: self.model_matrix().into(),
             normal: cgmath::Matrix3::from(self.rotation).into(),
         }
     }
@@ -88,3 +93,82 @@ impl crate::model::Vertex for InstanceRaw {
         Self::VERTEX_BUFFER_LAYOUT
     }
 }
+
+/// GPU copy of a node's instances
+/// Grows when instances are added, otherwise only the changed ranges are written
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    // Number of instances the buffer can hold
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    pub const STRIDE: wgpu::BufferAddress =
+        std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        // Empty buffers can't be bound, keep room for at least one instance
+        let capacity = capacity.max(1);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: capacity as wgpu::BufferAddress * Self::STRIDE,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer, capacity }
+    }
+
+    /// Uploads the `dirty` ranges of `instances`
+    /// The buffer is recreated (twice as big) and filled entirely when they don't fit anymore
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[Instance],
+        dirty: &[Range<usize>],
+    ) {
+        if let Some(capacity) = Self::grown_capacity(self.capacity, instances.len()) {
+            *self = Self::new(device, capacity);
+            self.write(queue, instances, 0..instances.len());
+            return;
+        }
+
+        for range in dirty {
+            self.write(queue, instances, range.clone());
+        }
+    }
+
+    // The capacity of the new buffer when `len` instances don't fit in `capacity` anymore
+    fn grown_capacity(capacity: usize, len: usize) -> Option<usize> {
+        (len > capacity).then(|| len.next_power_of_two())
+    }
+
+    fn write(&self, queue: &wgpu::Queue, instances: &[Instance], range: Range<usize>) {
+        let data = instances[range.clone()]
+            .iter()
+            .map(Instance::to_raw)
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.buffer,
+            range.start as wgpu::BufferAddress * Self::STRIDE,
+            bytemuck::cast_slice(&data),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growth() {
+        assert_eq!(InstanceBuffer::grown_capacity(4, 3), None);
+        assert_eq!(InstanceBuffer::grown_capacity(4, 4), None);
+        assert_eq!(InstanceBuffer::grown_capacity(4, 5), Some(8));
+        assert_eq!(InstanceBuffer::grown_capacity(8, 9), Some(16));
+        // Past the next power of two at once
+        assert_eq!(InstanceBuffer::grown_capacity(1, 100), Some(128));
+        assert_eq!(InstanceBuffer::grown_capacity(3, 4), Some(4));
+    }
+}
@@ -1,339 +1,59 @@
 use std::path::Path;
 
-use ::instant::Instant;
 use cgmath::prelude::*;
-use context::GraphicsContext;
-use node::Node;
-use pass::{phong::PhongPass, Pass};
-
-use winit::{dpi::PhysicalPosition, event::*};
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
-mod camera;
-mod context;
-mod instance;
+pub mod bounds;
+pub mod camera;
+pub mod capture;
+pub mod context;
+pub mod cubemap;
+pub mod engine;
+pub mod graph;
+pub mod instance;
 mod instant;
-mod model;
-mod node;
-mod particle;
-mod pass;
-mod primitives;
-mod resources;
-mod texture;
-mod window;
-
-use crate::particle::ParticleSystem;
-use crate::{
-    camera::{Camera, CameraController},
-    model::Keyframes,
-    pass::phong::{Locals, PhongConfig},
-    primitives::{sphere::generate_sphere, PrimitiveMesh},
-    window::Window,
+pub mod light;
+pub mod model;
+pub mod node;
+pub mod particle;
+pub mod pass;
+pub mod post;
+pub mod primitives;
+pub mod resources;
+pub mod scene;
+pub mod scene_file;
+pub mod texture;
+pub mod window;
+
+pub use crate::{
+    camera::Camera,
+    capture::Capture,
+    engine::{Engine, EngineBuilder},
+    instance::Instance,
+    light::{Light, LightKind, ShadowConfig},
+    node::{Node, NodeId, Nodes, Transform},
+    particle::{
+        Burst, Curve, EmitterShape, MeshSurface, ParticleAppearance, ParticleBlend,
+        ParticleEmitter, ParticleForces,
+    },
+    pass::{
+        culling::CullingStats,
+        lights::LightUniform,
+        phong::{Locals, PhongConfig, Shading},
+        scene::{ScenePass, SceneShader},
+        Pass,
+    },
+    scene::{
+        LightDescriptor, ModelSource, NodeDescriptor, ParticleSystemDescriptor, Scene, SkyboxSource,
+    },
+    scene_file::{SceneFile, SceneFormat},
 };
-use crate::{instance::Instance, window::WindowEvents};
 
 pub use std::time::Duration;
 
-struct State {
-    ctx: GraphicsContext,
-    pass: PhongPass,
-    // Window size
-    size: winit::dpi::PhysicalSize<u32>,
-    // Camera
-    camera: Camera,
-    camera_controller: CameraController,
-    // The 3D models in the scene (as Nodes)
-    nodes: Vec<Node>,
-    particle_system: Vec<ParticleSystem>,
-    // Animation
-    time: Instant,
-}
-
-impl State {
-    // Initialize the state
-    async fn new(window: &Window) -> Self {
-        // Save the window size for use later
-        let size = window.window.inner_size();
-
-        // Initialize the graphic context
-        let ctx = GraphicsContext::new(window).await;
-
-        // Setup the camera and it's initial position
-        let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let camera_controller = camera::CameraController::new(4.0, 0.4);
-
-        // Initialize the pass
-        let pass_config = PhongConfig {
-            max_lights: 1,
-            ambient: Default::default(),
-            wireframe: false,
-        };
-
-        let (sphere_vertices, sphere_indices) = generate_sphere(0.5f32, 36, 18);
-        let light_model =
-            PrimitiveMesh::new(&ctx.device, &ctx.queue, &sphere_vertices, &sphere_indices).await;
-
-        let pass = PhongPass::new(
-            &pass_config,
-            &ctx.device,
-            &ctx.queue,
-            &ctx.config,
-            &camera,
-            Some(light_model.model),
-        );
-
-        // Create the 3D objects!
-        // Load 3D model from disk or as a HTTP request (for web support)
-        let ferris_model = resources::load_model(
-            &Path::new("ferris").join("ferris.obj"),
-            &ctx.device,
-            &ctx.queue,
-        )
-        .await
-        .expect("Couldn't load model. Maybe path is wrong?");
-
-        let car_model = resources::load_model(Path::new("car.glb"), &ctx.device, &ctx.queue)
-            .await
-            .unwrap();
-
-        // Create instances for each object with locational data (position + rotation)
-        // Renderer currently defaults to using instances. Want one object? Pass a Vec of 1 instance.
-
-        // We create a 2x2 grid of objects by doing 1 nested loop here
-        // And use the "displacement" matrix above to offset objects with a gap
-        const SPACE_BETWEEN: f32 = 3.0;
-        const NUM_INSTANCES_PER_ROW: u32 = 10;
-        // More "manual" placement as an example
-        let ferris_instances = (0..2)
-            .map(|z| {
-                let position = cgmath::Vector3 {
-                    x: if z == 0 { 0.3 } else { -0.3 },
-                    y: 1.2,
-                    z: -0.2,
-                };
-                let scale = cgmath::Vector3::new(0.5f32, 0.5f32, 0.5f32);
-                let rotation = cgmath::Quaternion::from_axis_angle(
-                    cgmath::Vector3::unit_x(),
-                    cgmath::Deg(if z == 0 { 0f32 } else { -25.0 }),
-                );
-                Instance {
-                    position,
-                    rotation,
-                    scale,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let car_instances = (0..1)
-            .map(|z| {
-                let z = SPACE_BETWEEN * (z as f32);
-                let position = cgmath::Vector3 { x: z, y: 1.0, z };
-                let scale = cgmath::Vector3::new(1f32, 1f32, 1f32);
-                let rotation = cgmath::Quaternion::from_axis_angle(
-                    cgmath::Vector3::unit_y(),
-                    cgmath::Deg(0.0),
-                );
-                Instance {
-                    position,
-                    rotation,
-                    scale,
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let ferris_node = Node {
-            parent: 0,
-            locals: Locals {
-                position: [0f32, 0f32, -0.2f32, 0f32],
-                color: [0f32; 4], // Color is not used yet
-                normal: [0f32; 4],
-                lights: [0f32; 4],
-            },
-            model: ferris_model,
-            instances: ferris_instances,
-        };
-
-        let car_node = Node {
-            parent: 0,
-            locals: Locals {
-                position: [0f32; 4],
-                color: [0f32; 4], // Color is not used yet
-                normal: [0f32; 4],
-                lights: [0f32; 4],
-            },
-            model: car_model,
-            instances: car_instances,
-        };
-
-        // Put all our nodes into an Vector to loop over later
-        let nodes = vec![ferris_node, car_node];
-
-        let (sphere_vertices, sphere_indices) = generate_sphere(0.5f32, 36, 18);
-        let light_model =
-            PrimitiveMesh::new(&ctx.device, &ctx.queue, &sphere_vertices, &sphere_indices).await;
-
-        // Create a particle system
-        let particle_system = vec![ParticleSystem::new(
-            &ctx.device,
-            light_model.model,
-            Locals {
-                position: [0f32; 4],
-                color: [0f32; 4], // Color is not used yet
-                normal: [0f32; 4],
-                lights: [0f32; 4],
-            },
-            100,
-        )];
-
-        // Clear color used for mouse input interaction
-        let time = Instant::now();
-
-        Self {
-            ctx,
-            pass,
-            size,
-            camera,
-            camera_controller,
-            nodes,
-            particle_system,
-            time,
-        }
-    }
-
-    // Keeps state in sync with window size when changed
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.ctx.config.width = new_size.width;
-            self.ctx.config.height = new_size.height;
-            self.ctx
-                .surface
-                .configure(&self.ctx.device, &self.ctx.config);
-
-            self.pass.projection.resize(new_size.width, new_size.height);
-
-            // Make sure to current window size to depth texture - required for calc
-            self.pass.depth_texture = texture::Texture::create_depth_texture(
-                &self.ctx.device,
-                &self.ctx.config,
-                "depth_texture",
-            );
-        }
-    }
-
-    // Handle input using WindowEvent
-    pub fn keyboard(&mut self, state: ElementState, keycode: &VirtualKeyCode) -> bool {
-        // Send any input to camera controller
-        self.camera_controller.process_keyboard(*keycode, state)
-    }
-
-    pub fn mouse_moved(&mut self, position: PhysicalPosition<f64>) {
-        self.camera_controller.process_mouse(position);
-    }
-
-    pub fn mouse_input(&mut self, state: &ElementState, button: &MouseButton) {
-        self.camera_controller.process_mouse_input(state, button);
-    }
-
-    pub fn scroll(&mut self, delta: &MouseScrollDelta) {
-        self.camera_controller.process_scroll(delta);
-    }
-
-    fn update(&mut self, dt: Duration) {
-        // Sync local app state with camera
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.pass
-            .camera_uniform
-            .update_view_proj(&self.camera, &self.pass.projection);
-        self.ctx.queue.write_buffer(
-            &self.pass.global_uniform_buffer,
-            0,
-            bytemuck::cast_slice(&[self.pass.camera_uniform]),
-        );
-
-        // Update the light
-        let old_position: cgmath::Vector3<_> = self.pass.light_uniform.position.into();
-        self.pass.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
-        self.ctx.queue.write_buffer(
-            &self.pass.light_buffer,
-            0,
-            bytemuck::cast_slice(&[self.pass.light_uniform]),
-        );
-
-        // Update the particle system
-        for particle in &mut self.particle_system {
-            particle.update(dt, &self.ctx.queue);
-        }
-
-        #[cfg(debug_assertions)]
-        log::debug!("Time elapsed: {:?}", &self.time.elapsed());
-
-        // Update local uniforms
-        let current_time = &self.time.elapsed().as_secs_f32();
-        for (node_index, node) in self.nodes.iter_mut().enumerate() {
-            // Play animations
-            if !node.model.animations.is_empty() {
-                // Loop through all animations
-                // TODO: Ideally we'd play a certain animation by name - we assume first one for now
-                let mut current_keyframe_index = 0;
-                for animation in &node.model.animations {
-                    for timestamp in &animation.timestamps {
-                        if timestamp > current_time {
-                            break;
-                        }
-                        if current_keyframe_index < &animation.timestamps.len() - 1 {
-                            current_keyframe_index += 1;
-                        }
-                    }
-                }
-
-                // Update locals with current animation
-                let current_animation = &node.model.animations[0].keyframes;
-                let mut current_frame: Option<&Vec<f32>> = None;
-                match current_animation {
-                    Keyframes::Translation(frames) => {
-                        current_frame = Some(&frames[current_keyframe_index])
-                    }
-                    Keyframes::Other => (),
-                }
-
-                if let Some(current_frame) = current_frame {
-                    node.locals.position = [
-                        current_frame[0],
-                        current_frame[1],
-                        current_frame[2],
-                        node.locals.position[3],
-                    ];
-                }
-            }
-
-            self.pass
-                .uniform_pool
-                .update_uniform(node_index, node.locals, &self.ctx.queue);
-        }
-    }
-
-    // Primary render flow
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        if let Err(err) = self.pass.draw(
-            &self.ctx.surface,
-            &self.ctx.device,
-            &self.ctx.queue,
-            &self.nodes,
-            &self.particle_system,
-        ) {
-            log::error!("Error in draw: {:?}", err);
-        }
-
-        Ok(())
-    }
-}
-
+/// The demo scene: two ferris, a car, a rotating light and some particles
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
     pub fn init_logs() {
@@ -354,43 +74,34 @@ pub async fn run() {
 
     init_logs();
 
-    let window = Window::new();
-
-    // State::new uses async code, so we're going to wait for it to finish
-    let mut app = State::new(&window).await;
-    let mut last_render_time = instant::Instant::now(); // NEW!
-
-    // @TODO: Wire up state methods again (like render)
-    window.run(move |event| match event {
-        WindowEvents::Resized { width, height } => {
-            app.resize(winit::dpi::PhysicalSize { width, height });
-        }
-        WindowEvents::Draw => {
-            let dt = last_render_time.elapsed();
-            last_render_time = instant::Instant::now();
-
-            app.update(dt);
-            if let Err(err) = app.render() {
-                log::error!("Error in rendering {:?}", err);
+    let scene = SceneFile::load(Path::new("scenes").join("demo.ron"))
+        .await
+        .expect("Couldn't load the demo scene");
+
+    let engine = Engine::builder()
+        .with_scene_file(scene)
+        .with_particle_system(ParticleSystemDescriptor {
+            emitter: ParticleEmitter {
+                shape: EmitterShape::Cone {
+                    radius: 0.1,
+                    angle: 20.0,
+                },
+                rate: 40.0,
+                speed: 2.0..3.0,
+                ..Default::default()
+            },
+            max_particles: 100,
+            ..Default::default()
+        })
+        // Rotate the lights around the scene
+        .on_update(|scene, _dt| {
+            let rotation =
+                cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0));
+            for light in &mut scene.lights {
+                let old_position: cgmath::Vector3<_> = light.position.into();
+                light.position = (rotation * old_position).into();
             }
-        }
-        WindowEvents::Keyboard {
-            state,
-            virtual_keycode,
-        } => {
-            app.keyboard(state, virtual_keycode);
-        }
-
-        WindowEvents::MouseWheel { delta } => {
-            app.scroll(delta);
-        }
-
-        WindowEvents::MouseMoved { position } => {
-            app.mouse_moved(*position);
-        }
+        });
 
-        WindowEvents::MouseInput { state, button } => {
-            app.mouse_input(state, button);
-        }
-    });
+    engine.run().await.expect("Couldn't run the demo scene");
 }
@@ -0,0 +1,222 @@
+use cgmath::{
+    ortho, perspective, EuclideanSpace, InnerSpace, Matrix4, Point3, Rad, Vector3, Vector4,
+};
+
+use crate::{
+    camera::{Camera, OPENGL_TO_WGPU_MATRIX},
+    node::{NodeId, Nodes},
+    pass::lights::LightUniform,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    // Lights everything from the same direction (like the sun), its position is ignored
+    Directional,
+    // Shines in every direction from its position
+    Point,
+    // Shines in a cone around its direction, fully lit inside `inner_angle`
+    // and fading out up to `outer_angle` (both from the cone axis)
+    Spot {
+        inner_angle: Rad<f32>,
+        outer_angle: Rad<f32>,
+    },
+}
+
+// How a light casts shadows (only directional and spot lights do)
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowConfig {
+    // Subtracted from the depth of the lit point, too low gives shadow acne
+    // and too high detaches the shadows from their casters
+    pub bias: f32,
+    // Offsets the lit point along its normal (world units), helps on surfaces facing away
+    pub normal_bias: f32,
+    // Directional lights cover this distance around the camera,
+    // spot lights stop casting shadows past it (or past their range)
+    pub distance: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            bias: 0.0005,
+            normal_bias: 0.02,
+            distance: 20.0,
+        }
+    }
+}
+
+// A light of the scene
+// Point and spot lights fall off with the square of the distance, and smoothly reach 0 at `range`
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind: LightKind,
+    // Relative to `parent` when the light is attached to a node
+    pub position: [f32; 3],
+    // Where directional and spot lights shine to (rotated with `parent` too)
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+    // Infinite by default
+    pub range: f32,
+    // Node the light follows (None to stay in place)
+    pub parent: Option<NodeId>,
+    // None for a light that doesn't cast shadows
+    pub shadow: Option<ShadowConfig>,
+}
+
+impl Light {
+    /// A point light of intensity 1 and infinite range
+    pub fn new(position: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: [0.0, -1.0, 0.0],
+            color,
+            intensity: 1.0,
+            range: f32::INFINITY,
+            parent: None,
+            shadow: None,
+        }
+    }
+
+    pub fn directional(direction: [f32; 3], color: [f32; 3]) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            direction,
+            ..Self::new([0.0; 3], color)
+        }
+    }
+
+    pub fn spot<A: Into<Rad<f32>>>(
+        position: [f32; 3],
+        direction: [f32; 3],
+        color: [f32; 3],
+        inner_angle: A,
+        outer_angle: A,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                inner_angle: inner_angle.into(),
+                outer_angle: outer_angle.into(),
+            },
+            direction,
+            ..Self::new(position, color)
+        }
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_range(mut self, range: f32) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn with_shadow(mut self, shadow: ShadowConfig) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
+
+    /// The light as it should be uploaded, in world space
+    /// (the camera decides where directional lights cast shadows)
+    pub fn to_uniform(&self, nodes: &Nodes, camera: &Camera) -> LightUniform {
+        let mut position = Vector3::from(self.position);
+        let mut direction = Vector3::from(self.direction);
+        if let Some(parent) = self.parent.and_then(|parent| nodes.get(parent)) {
+            let world = parent.world_matrix();
+            position = (world * position.extend(1.0)).truncate();
+            direction =
+                (world * Vector4::new(direction.x, direction.y, direction.z, 0.0)).truncate();
+        }
+        // A zero direction would turn into NaNs in the shader
+        if direction.magnitude2() > 0.0 {
+            direction = direction.normalize();
+        }
+
+        let (kind, cone) = match self.kind {
+            LightKind::Directional => (0, [1.0, 1.0]),
+            LightKind::Point => (1, [1.0, 1.0]),
+            LightKind::Spot {
+                inner_angle,
+                outer_angle,
+            } => {
+                // The inner cone has to be strictly narrower than the outer one,
+                // the shader's `smoothstep` is undefined when its edges are equal
+                let outer = outer_angle.0.cos();
+                (2, [inner_angle.0.cos().max(outer + 1e-4), outer])
+            }
+        };
+
+        // Shadow maps are given to the casters by the pass, 0 only marks them
+        let (shadow, view_proj, shadow_config) = match (self.shadow, self.kind) {
+            (Some(shadow), LightKind::Directional) => {
+                let view_proj = directional_view_proj(direction, camera.position, shadow.distance);
+                (0, view_proj, shadow)
+            }
+            (Some(shadow), LightKind::Spot { outer_angle, .. }) => {
+                let far = self.range.min(shadow.distance);
+                let view_proj = spot_view_proj(position, direction, outer_angle, far);
+                (0, view_proj, shadow)
+            }
+            _ => (-1, Matrix4::from_scale(1.0), ShadowConfig::default()),
+        };
+
+        LightUniform {
+            position: position.into(),
+            kind,
+            color: self.color,
+            intensity: self.intensity,
+            direction: direction.into(),
+            range: self.range,
+            cone,
+            shadow,
+            shadow_bias: shadow_config.bias,
+            view_proj: view_proj.into(),
+            normal_bias: shadow_config.normal_bias,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+// Any vector that isn't parallel to the light direction works as "up"
+fn light_up(direction: Vector3<f32>) -> Vector3<f32> {
+    if direction.y.abs() > 0.99 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+// An orthographic box of `distance` around `center`, deep enough
+// to keep the casters that are further away than it towards the light
+fn directional_view_proj(
+    direction: Vector3<f32>,
+    center: Point3<f32>,
+    distance: f32,
+) -> Matrix4<f32> {
+    let view = Matrix4::look_to_rh(center, direction, light_up(direction));
+    let proj = ortho(
+        -distance,
+        distance,
+        -distance,
+        distance,
+        -2.0 * distance,
+        distance,
+    );
+    OPENGL_TO_WGPU_MATRIX * proj * view
+}
+
+fn spot_view_proj(
+    position: Vector3<f32>,
+    direction: Vector3<f32>,
+    outer_angle: Rad<f32>,
+    far: f32,
+) -> Matrix4<f32> {
+    let view = Matrix4::look_to_rh(Point3::from_vec(position), direction, light_up(direction));
+    // Wide cones would need a huge (or impossible) field of view
+    let fovy = Rad((outer_angle.0 * 2.0).min(3.0));
+    OPENGL_TO_WGPU_MATRIX * perspective(fovy, 1.0, 0.05, far.max(0.1)) * view
+}
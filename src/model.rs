@@ -2,18 +2,21 @@ use std::ops::Range;
 
 use wgpu::BindGroup;
 
-use crate::texture;
+use crate::{bounds::Bounds, pass::MaterialPipelines, texture};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    // Direction of increasing u, w is the handedness of the bitangent (glTF's TANGENT)
+    // Zero when unknown, see `compute_tangents`
+    pub tangent: [f32; 4],
 }
 
 impl ModelVertex {
@@ -36,6 +39,11 @@ impl ModelVertex {
                 shader_location: 2,
                 format: wgpu::VertexFormat::Float32x3,
             },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32x4,
+            },
         ],
     };
 }
@@ -46,18 +54,157 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// Generates the tangents of an indexed triangle list from its positions, normals and UVs
+///
+/// Like MikkTSpace, the tangent of each triangle follows its UVs, is weighted by the angle
+/// at each corner, then orthogonalized against the vertex normal. The handedness (`w`)
+/// tells whether the bitangent is `cross(normal, tangent)` or its opposite (mirrored UVs).
+pub fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    use cgmath::{InnerSpace, Vector3, Zero};
+
+    let mut tangents = vec![Vector3::zero(); vertices.len()];
+    let mut bitangents = vec![Vector3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(vertices[i].position));
+        let [ta, tb, tc] = [a, b, c].map(|i| vertices[i].tex_coords);
+
+        let (edge1, edge2) = (pb - pa, pc - pa);
+        let (du1, dv1) = (tb[0] - ta[0], tb[1] - ta[1]);
+        let (du2, dv2) = (tc[0] - ta[0], tc[1] - ta[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        // Degenerate UVs, the vertices fall back to an arbitrary tangent below
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+        let tangent = ((edge1 * dv2 - edge2 * dv1) / det).normalize();
+        let bitangent = ((edge2 * du1 - edge1 * du2) / det).normalize();
+
+        for (vertex, (from, to)) in [(a, (pb, pc)), (b, (pc, pa)), (c, (pa, pb))] {
+            let position = Vector3::from(vertices[vertex].position);
+            let (to_from, to_to) = (from - position, to - position);
+            if to_from.is_zero() || to_to.is_zero() {
+                continue;
+            }
+            let angle = to_from.angle(to_to).0;
+            if angle.is_nan() {
+                continue;
+            }
+            tangents[vertex] += tangent * angle;
+            bitangents[vertex] += bitangent * angle;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vector3::from(vertex.normal);
+        if normal.is_zero() {
+            vertex.tangent = [1.0, 0.0, 0.0, 1.0];
+            continue;
+        }
+        // Gram-Schmidt, the tangent has to be perpendicular to the normal
+        let mut t = tangent - normal * normal.dot(tangent);
+        if t.magnitude2() < f32::EPSILON {
+            // Any direction perpendicular to the normal will do
+            let axis = if normal.x.abs() < 0.9 {
+                Vector3::unit_x()
+            } else {
+                Vector3::unit_y()
+            };
+            t = normal.cross(axis).cross(normal);
+        }
+        let t = t.normalize();
+        let handedness = if normal.cross(t).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = [t.x, t.y, t.z, handedness];
+    }
+}
+
+/// How the alpha of the base color is used (glTF's `alphaMode`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    // Alpha is ignored
+    #[default]
+    Opaque,
+    // Fully transparent below `Material::alpha_cutoff`, opaque above
+    Mask,
+    // Blended with what is behind
+    Blend,
+}
+
 pub struct Material {
     pub name: String,
+    // Base color of the metallic-roughness model
     pub diffuse_texture: texture::Texture,
     // pub bind_group: wgpu::BindGroup,
+    // Multiplies the diffuse texture (MTL's `Kd` and `d`)
+    pub base_color_factor: [f32; 4],
+    // Blinn-Phong highlights (MTL's `Ks` and `Ns`), used by the Phong shading
+    pub specular_color: [f32; 3],
+    pub shininess: f32,
+    // Metallic-roughness data (from glTF), the textures are multiplied by their factor
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: [f32; 3],
+    // How much the occlusion texture darkens the ambient light
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    // Only used with `AlphaMode::Mask`
+    pub alpha_cutoff: f32,
+    // Back faces are drawn (and lit from their side) instead of culled
+    pub double_sided: bool,
+    // Linear, roughness in green and metalness in blue
+    pub metallic_roughness_texture: Option<texture::Texture>,
+    // Linear, occlusion in red
+    pub occlusion_texture: Option<texture::Texture>,
+    pub emissive_texture: Option<texture::Texture>,
+    // Linear, tangent space normals (OpenGL convention, green is up)
+    pub normal_texture: Option<texture::Texture>,
+    // Scales the x and y of the sampled normals (PBR only)
+    pub normal_scale: f32,
+    // Multiplies the specular color (MTL's `map_Ks`)
+    pub specular_texture: Option<texture::Texture>,
+}
+
+impl Material {
+    /// An opaque, dielectric, fully rough material that only has a color texture
+    pub fn new(name: impl Into<String>, diffuse_texture: texture::Texture) -> Self {
+        Self {
+            name: name.into(),
+            diffuse_texture,
+            base_color_factor: [1.0; 4],
+            specular_color: [1.0; 3],
+            shininess: 32.0,
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+            emissive_factor: [0.0; 3],
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            metallic_roughness_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            specular_texture: None,
+        }
+    }
 }
 
 pub struct Mesh {
     pub name: String,
+    // Can be copied from, indirect draws gather the meshes in shared buffers (see `MeshArena`)
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
+    pub num_vertices: u32,
     pub material: usize,
+    // In model space, the camera culls with them and transparent meshes are sorted by their center
+    pub bounds: Bounds,
 }
 
 pub enum Keyframes {
@@ -77,6 +224,28 @@ pub struct Model {
     pub animations: Vec<AnimationClip>,
 }
 
+impl Model {
+    /// Points the meshes without a known material to a plain white one, added after the others,
+    /// so that every mesh has a material (and bind group) to be drawn with
+    pub fn add_default_material(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let default = self.materials.len();
+        let mut missing = false;
+        for mesh in &mut self.meshes {
+            if mesh.material >= default {
+                mesh.material = default;
+                missing = true;
+            }
+        }
+        if missing {
+            let white = texture::Texture::from_color(device, queue, [255; 4], "Default");
+            self.materials.push(Material::new("Default", white));
+        }
+    }
+}
+
+// `pipelines` picks the pipeline of each material (double-sidedness), blended materials
+// are then skipped as the transparent phase draws them sorted (see `TransparentPhase`)
+// Without them the current pipeline is used for everything (shadows for instance)
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -90,6 +259,7 @@ pub trait DrawModel<'a> {
         material: &'a Material,
         instances: Range<u32>,
         local_bind_group: &'a wgpu::BindGroup,
+        pipelines: Option<&'a MaterialPipelines>,
     );
 
     fn draw_model(&mut self, model: &'a Model, local_bind_group: &'a wgpu::BindGroup);
@@ -98,7 +268,8 @@ pub trait DrawModel<'a> {
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
-        local_bind_group: &Vec<&'a wgpu::BindGroup>,
+        local_bind_group: &[&'a wgpu::BindGroup],
+        pipelines: Option<&'a MaterialPipelines>,
     );
 }
 
@@ -112,67 +283,74 @@ where
         material: &'b Material,
         local_bind_group: &'b wgpu::BindGroup,
     ) {
-        self.draw_mesh_instanced(mesh, material, 0..1, local_bind_group);
+        self.draw_mesh_instanced(mesh, material, 0..1, local_bind_group, None);
     }
 
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
-        _material: &'b Material,
+        material: &'b Material,
         instances: Range<u32>,
         local_bind_group: &'b wgpu::BindGroup,
+        pipelines: Option<&'b MaterialPipelines>,
     ) {
+        if let Some(pipelines) = pipelines {
+            self.set_pipeline(pipelines.get(material));
+        }
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        // The factors of the material are part of its bind group
         self.set_bind_group(1, local_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
     fn draw_model(&mut self, model: &'b Model, local_bind_group: &'b wgpu::BindGroup) {
-        self.draw_model_instanced(model, 0..1, &vec![local_bind_group]);
+        self.draw_model_instanced(model, 0..1, &[local_bind_group], None);
     }
 
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
         instances: Range<u32>,
-        local_bind_group: &Vec<&'b BindGroup>,
+        local_bind_group: &[&'b BindGroup],
+        pipelines: Option<&'b MaterialPipelines>,
     ) {
         for mesh in &model.meshes {
-            let material = &model.materials[mesh.material];
-            let material_bind_group = local_bind_group[mesh.material];
-            self.draw_mesh_instanced(mesh, material, instances.clone(), &material_bind_group);
+            let (Some(material), Some(&material_bind_group)) = (
+                model.materials.get(mesh.material),
+                local_bind_group.get(mesh.material),
+            ) else {
+                continue;
+            };
+            if pipelines.is_some() && material.alpha_mode == AlphaMode::Blend {
+                continue;
+            }
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                material_bind_group,
+                pipelines,
+            );
         }
     }
 }
 
 pub trait DrawLight<'a> {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'a Mesh,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
+    fn draw_light_mesh(&mut self, mesh: &'a Mesh, global_bind_group: &'a wgpu::BindGroup);
     fn draw_light_mesh_instanced(
         &mut self,
         mesh: &'a Mesh,
         instances: Range<u32>,
         global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
     );
 
-    fn draw_light_model(
-        &mut self,
-        model: &'a Model,
-        global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
-    );
+    fn draw_light_model(&mut self, model: &'a Model, global_bind_group: &'a wgpu::BindGroup);
     fn draw_light_model_instanced(
         &mut self,
         model: &'a Model,
         instances: Range<u32>,
         global_bind_group: &'a wgpu::BindGroup,
-        local_bind_group: &'a wgpu::BindGroup,
     );
 }
 
@@ -180,13 +358,8 @@ impl<'a, 'b> DrawLight<'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_light_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_mesh_instanced(mesh, 0..1, global_bind_group, local_bind_group);
+    fn draw_light_mesh(&mut self, mesh: &'b Mesh, global_bind_group: &'b wgpu::BindGroup) {
+        self.draw_light_mesh_instanced(mesh, 0..1, global_bind_group);
     }
 
     fn draw_light_mesh_instanced(
@@ -194,37 +367,24 @@ where
         mesh: &'b Mesh,
         instances: Range<u32>,
         global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, global_bind_group, &[]);
-        self.set_bind_group(1, local_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
-    fn draw_light_model(
-        &mut self,
-        model: &'b Model,
-        global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
-    ) {
-        self.draw_light_model_instanced(model, 0..1, global_bind_group, local_bind_group);
+    fn draw_light_model(&mut self, model: &'b Model, global_bind_group: &'b wgpu::BindGroup) {
+        self.draw_light_model_instanced(model, 0..1, global_bind_group);
     }
     fn draw_light_model_instanced(
         &mut self,
         model: &'b Model,
         instances: Range<u32>,
         global_bind_group: &'b wgpu::BindGroup,
-        local_bind_group: &'b wgpu::BindGroup,
     ) {
         for mesh in &model.meshes {
-            self.draw_light_mesh_instanced(
-                mesh,
-                instances.clone(),
-                global_bind_group,
-                local_bind_group,
-            );
+            self.draw_light_mesh_instanced(mesh, instances.clone(), global_bind_group);
         }
     }
 }
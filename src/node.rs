@@ -1,14 +1,561 @@
+use std::ops::Range;
+
+use cgmath::{Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3};
+
 use crate::{instance::Instance, model, pass::phong::Locals};
 
+// Local placement of a node, relative to its parent
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_position(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+// Instance indices changed since the last upload
+#[derive(Default)]
+struct DirtyRanges {
+    all: bool,
+    ranges: Vec<Range<usize>>,
+}
+
+impl DirtyRanges {
+    fn all() -> Self {
+        Self {
+            all: true,
+            ranges: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, range: Range<usize>) {
+        if !self.all {
+            self.ranges.push(range);
+        }
+    }
+
+    // Clamps the ranges to `len`, and merges the ones that overlap or touch
+    fn into_ranges(mut self, len: usize) -> Vec<Range<usize>> {
+        if self.all {
+            self.ranges.clear();
+            self.ranges.push(0..len);
+        }
+
+        self.ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges {
+            let range = range.start.min(len)..range.end.min(len);
+            if range.is_empty() {
+                continue;
+            }
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+/// Stable handle to a node in the scene
+/// It stays valid until the node is removed, and is never reused afterwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
 // This represents a 3D model in a scene.
-// It contains the 3D model, instance data, and a parent ID (TBD)
+// It contains the 3D model, instance data, and its place in the scene graph
 pub struct Node {
-    // ID of parent Node
-    pub parent: u32,
-    // Local position of model (for relative calculations)
-    pub locals: Locals,
+    // Parent in the scene graph (None for root nodes)
+    parent: Option<NodeId>,
+    // Placement relative to the parent
+    transform: Transform,
+    // Cached parent world matrix * local transform, only recomputed when dirty
+    world_matrix: Matrix4<f32>,
+    dirty: bool,
     // The vertex buffers and texture data
     pub model: model::Model,
     // An array of positional data for each instance (can just pass 1 instance)
-    pub instances: Vec<Instance>,
+    instances: Vec<Instance>,
+    // Instances changed since the last upload
+    dirty_instances: DirtyRanges,
+}
+
+impl Node {
+    pub fn new(model: model::Model, transform: Transform, instances: Vec<Instance>) -> Self {
+        Self {
+            parent: None,
+            transform,
+            world_matrix: Matrix4::identity(),
+            dirty: true,
+            model,
+            dirty_instances: DirtyRanges::all(),
+            instances,
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn set_parent(&mut self, parent: Option<NodeId>) {
+        self.parent = parent;
+        self.dirty = true;
+    }
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    // Marks the node dirty, its world matrix (and its children's) are recomputed next update
+    pub fn transform_mut(&mut self) -> &mut Transform {
+        self.dirty = true;
+        &mut self.transform
+    }
+
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+        self.dirty = true;
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// Gives access to a single instance, only this one is uploaded again
+    pub fn instance_mut(&mut self, index: usize) -> Option<&mut Instance> {
+        let instance = self.instances.get_mut(index)?;
+        self.dirty_instances.insert(index..index + 1);
+        Some(instance)
+    }
+
+    /// Gives access to every instance, they are all uploaded again
+    pub fn instances_mut(&mut self) -> &mut Vec<Instance> {
+        self.dirty_instances = DirtyRanges::all();
+        &mut self.instances
+    }
+
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        self.instances = instances;
+        self.dirty_instances = DirtyRanges::all();
+    }
+
+    pub fn push_instance(&mut self, instance: Instance) {
+        self.dirty_instances
+            .insert(self.instances.len()..self.instances.len() + 1);
+        self.instances.push(instance);
+    }
+
+    /// Removes an instance, the last one takes its place
+    pub fn swap_remove_instance(&mut self, index: usize) -> Instance {
+        let instance = self.instances.swap_remove(index);
+        if index < self.instances.len() {
+            self.dirty_instances.insert(index..index + 1);
+        }
+        instance
+    }
+
+    /// Ranges of instances to upload, sorted and merged (empty when nothing changed)
+    pub(crate) fn take_dirty_instances(&mut self) -> Vec<Range<usize>> {
+        std::mem::take(&mut self.dirty_instances).into_ranges(self.instances.len())
+    }
+
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.world_matrix
+    }
+
+    pub(crate) fn locals(&self) -> Locals {
+        // Normals need the inverse transpose to stay correct under non-uniform scale
+        let upper = Matrix3::from_cols(
+            self.world_matrix.x.truncate(),
+            self.world_matrix.y.truncate(),
+            self.world_matrix.z.truncate(),
+        );
+        let normal = upper
+            .invert()
+            .map(|inverse| inverse.transpose())
+            .unwrap_or_else(Matrix3::identity);
+
+        Locals {
+            model: self.world_matrix.into(),
+            normal: Matrix4::from(normal).into(),
+            ..Default::default()
+        }
+    }
+}
+
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// The nodes of a scene, addressed by `NodeId`
+/// Removing a node frees its slot for later inserts without moving the other nodes
+#[derive(Default)]
+pub struct Nodes {
+    slots: Vec<Slot>,
+    // Indices of the empty slots
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl Nodes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, mut node: Node) -> NodeId {
+        // A node may come back from `remove`, everything has to be uploaded again
+        node.dirty = true;
+        node.dirty_instances = DirtyRanges::all();
+        self.len += 1;
+
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// Removes a node, its children become root nodes
+    pub fn remove(&mut self, id: NodeId) -> Option<Node> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let node = slot.node.take()?;
+        // Handles to this slot are stale from now on
+        slot.generation += 1;
+        self.free.push(id.index);
+        self.len -= 1;
+
+        for (_, child) in self.iter_mut() {
+            if child.parent == Some(id) {
+                child.set_parent(None);
+            }
+        }
+
+        Some(node)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the nodes in slot order (insertion order until a node is removed)
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = NodeId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.node.as_ref().map(|node| (id, node))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (NodeId, &mut Node)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let id = NodeId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                slot.node.as_mut().map(|node| (id, node))
+            })
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    /// Recomputes the world matrices of dirty nodes and of the children of dirty nodes
+    /// Returns the nodes whose world matrix changed
+    pub(crate) fn update_world_matrices(&mut self) -> Vec<NodeId> {
+        let mut visits = vec![Visit::Pending; self.slots.len()];
+        for index in 0..self.slots.len() {
+            if self.slots[index].node.is_some() {
+                self.update_world_matrix(index, &mut visits);
+            }
+        }
+
+        visits
+            .into_iter()
+            .enumerate()
+            .filter(|(_, visit)| matches!(visit, Visit::Done { changed: true }))
+            .map(|(index, _)| NodeId {
+                index: index as u32,
+                generation: self.slots[index].generation,
+            })
+            .collect()
+    }
+
+    fn update_world_matrix(&mut self, index: usize, visits: &mut [Visit]) -> bool {
+        match visits[index] {
+            Visit::Done { changed } => return changed,
+            Visit::InProgress => {
+                log::error!("Node#{} is its own ancestor, ignoring its parent", index);
+                return false;
+            }
+            Visit::Pending => visits[index] = Visit::InProgress,
+        }
+
+        // Parents are resolved first so their world matrix is up to date
+        let node = self.slots[index]
+            .node
+            .as_ref()
+            .expect("Visited an empty slot");
+        let parent = node
+            .parent
+            .filter(|parent| self.contains(*parent))
+            .map(|parent| parent.index as usize);
+        let parent_changed = match parent {
+            Some(parent) => self.update_world_matrix(parent, visits),
+            None => false,
+        };
+
+        let node = self.slots[index]
+            .node
+            .as_ref()
+            .expect("Visited an empty slot");
+        let changed = node.dirty || parent_changed;
+        if changed {
+            let parent_world = match parent {
+                Some(parent) if visits[parent] != Visit::InProgress => {
+                    self.slots[parent]
+                        .node
+                        .as_ref()
+                        .expect("Parent was checked")
+                        .world_matrix
+                }
+                _ => Matrix4::identity(),
+            };
+            let node = self.slots[index]
+                .node
+                .as_mut()
+                .expect("Visited an empty slot");
+            node.world_matrix = parent_world * node.transform.to_matrix();
+            node.dirty = false;
+        }
+
+        visits[index] = Visit::Done { changed };
+        changed
+    }
+}
+
+impl std::ops::Index<NodeId> for Nodes {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        self.get(id)
+            .expect("No node for this NodeId (was it removed?)")
+    }
+}
+
+impl std::ops::IndexMut<NodeId> for Nodes {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        self.get_mut(id)
+            .expect("No node for this NodeId (was it removed?)")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    Pending,
+    InProgress,
+    Done { changed: bool },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(position: [f32; 3]) -> Node {
+        let model = model::Model {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            animations: Vec::new(),
+        };
+        Node::new(model, Transform::from_position(position.into()), Vec::new())
+    }
+
+    fn translation(position: [f32; 3]) -> Matrix4<f32> {
+        Matrix4::from_translation(position.into())
+    }
+
+    fn dirty(ranges: &[Range<usize>]) -> DirtyRanges {
+        let mut dirty = DirtyRanges::default();
+        for range in ranges {
+            dirty.insert(range.clone());
+        }
+        dirty
+    }
+
+    #[test]
+    fn dirty_ranges() {
+        // Overlapping and unsorted
+        assert_eq!(
+            dirty(&[4..8, 0..2, 6..10, 1..3]).into_ranges(20),
+            vec![0..3, 4..10]
+        );
+        // Touching ones are merged, apart ones aren't
+        assert_eq!(dirty(&[0..2, 2..4, 5..6]).into_ranges(20), vec![0..4, 5..6]);
+        // Contained
+        assert_eq!(dirty(&[0..10, 2..4]).into_ranges(20), vec![0..10]);
+        // Past the end (removed instances) are clamped or dropped
+        assert_eq!(dirty(&[3..8, 10..12]).into_ranges(5), vec![3..5]);
+        assert!(dirty(&[]).into_ranges(5).is_empty());
+        // Everything, whatever else was inserted
+        let mut all = DirtyRanges::all();
+        all.insert(2..3);
+        assert_eq!(all.into_ranges(5), vec![0..5]);
+        assert!(DirtyRanges::all().into_ranges(0).is_empty());
+    }
+
+    #[test]
+    fn stale_ids() {
+        let mut nodes = Nodes::new();
+        let a = nodes.insert(node([0.0; 3]));
+        let b = nodes.insert(node([0.0; 3]));
+
+        assert!(nodes.remove(a).is_some());
+        assert!(nodes.get(a).is_none());
+        assert!(nodes.get_mut(a).is_none());
+        assert!(!nodes.contains(a));
+        assert!(nodes.remove(a).is_none());
+        assert!(nodes.contains(b));
+        assert_eq!(nodes.len(), 1);
+    }
+
+    #[test]
+    fn reused_slots() {
+        let mut nodes = Nodes::new();
+        let a = nodes.insert(node([0.0; 3]));
+        nodes.remove(a);
+        let b = nodes.insert(node([1.0; 3]));
+
+        // Same slot, newer generation, the old handle doesn't see the new node
+        assert_eq!(b.index, a.index);
+        assert_eq!(b.generation, a.generation + 1);
+        assert!(nodes.get(a).is_none());
+        assert_eq!(nodes.get(b).unwrap().transform().position, [1.0; 3].into());
+        assert_eq!(nodes.ids().collect::<Vec<_>>(), vec![b]);
+    }
+
+    #[test]
+    fn removed_parent() {
+        let mut nodes = Nodes::new();
+        let parent = nodes.insert(node([1.0, 0.0, 0.0]));
+        let child = nodes.insert(node([0.0, 1.0, 0.0]));
+        nodes[child].set_parent(Some(parent));
+        nodes.update_world_matrices();
+        assert_eq!(nodes[child].world_matrix(), translation([1.0, 1.0, 0.0]));
+
+        // The child becomes a root node, placed by its own transform only
+        nodes.remove(parent);
+        assert_eq!(nodes[child].parent(), None);
+        assert_eq!(nodes.update_world_matrices(), vec![child]);
+        assert_eq!(nodes[child].world_matrix(), translation([0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn world_matrices() {
+        let mut nodes = Nodes::new();
+        let child = nodes.insert(node([0.0, 0.0, 1.0]));
+        let parent = nodes.insert(node([1.0, 0.0, 0.0]));
+        let other = nodes.insert(node([0.0; 3]));
+        nodes[child].set_parent(Some(parent));
+        nodes.update_world_matrices();
+        assert_eq!(nodes[child].world_matrix(), translation([1.0, 0.0, 1.0]));
+
+        // Only the moved node and its children change
+        nodes[parent].transform_mut().position = [2.0, 0.0, 0.0].into();
+        let changed = nodes.update_world_matrices();
+        assert_eq!(changed, vec![child, parent]);
+        assert!(!changed.contains(&other));
+        assert_eq!(nodes[child].world_matrix(), translation([2.0, 0.0, 1.0]));
+        assert!(nodes.update_world_matrices().is_empty());
+    }
+
+    #[test]
+    fn cycles() {
+        let mut nodes = Nodes::new();
+        let a = nodes.insert(node([1.0, 0.0, 0.0]));
+        let b = nodes.insert(node([0.0, 1.0, 0.0]));
+        nodes[a].set_parent(Some(b));
+        nodes[b].set_parent(Some(a));
+
+        // The cycle is broken where it is found (at `b`, reached from `a`)
+        assert_eq!(nodes.update_world_matrices(), vec![a, b]);
+        assert_eq!(nodes[b].world_matrix(), translation([0.0, 1.0, 0.0]));
+        assert_eq!(nodes[a].world_matrix(), translation([1.0, 1.0, 0.0]));
+
+        let c = nodes.insert(node([0.0; 3]));
+        nodes[c].set_parent(Some(c));
+        assert_eq!(nodes.update_world_matrices(), vec![c]);
+        assert_eq!(nodes[c].world_matrix(), Matrix4::identity());
+    }
 }
@@ -0,0 +1,92 @@
+/// Values the curves can interpolate
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, amount: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: Self, amount: f32) -> Self {
+        self + (other - self) * amount
+    }
+}
+
+impl<const N: usize> Lerp for [f32; N] {
+    fn lerp(self, other: Self, amount: f32) -> Self {
+        std::array::from_fn(|i| self[i].lerp(other[i], amount))
+    }
+}
+
+/// A value varying over the life of a particle, from 0 (birth) to 1 (death)
+/// Linear between its keys, constant before the first one and after the last one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T> {
+    // Sorted by time
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Lerp> Curve<T> {
+    /// `keys` are (time, value) pairs, there must be at least one
+    pub fn new(mut keys: Vec<(f32, T)>) -> Self {
+        assert!(!keys.is_empty(), "A curve needs at least one key");
+        keys.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Self { keys }
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new(vec![(0.0, value)])
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Self::new(vec![(0.0, start), (1.0, end)])
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.keys.partition_point(|(key, _)| *key <= time);
+        match (self.keys.get(next.wrapping_sub(1)), self.keys.get(next)) {
+            (Some(&(start, a)), Some(&(end, b))) => a.lerp(b, (time - start) / (end - start)),
+            (Some(&(_, value)), None) | (None, Some(&(_, value))) => value,
+            (None, None) => unreachable!("Curves have at least one key"),
+        }
+    }
+
+    /// `N` samples evenly spaced from 0 to 1, the shaders interpolate between them
+    pub fn bake<const N: usize>(&self) -> [T; N] {
+        std::array::from_fn(|i| self.sample(i as f32 / (N - 1) as f32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample() {
+        // Given out of order
+        let curve = Curve::new(vec![(0.8, 2.0), (0.2, 1.0)]);
+        assert_eq!(curve.sample(0.0), 1.0);
+        assert_eq!(curve.sample(0.2), 1.0);
+        assert_eq!(curve.sample(0.5), 1.5);
+        assert_eq!(curve.sample(0.8), 2.0);
+        assert_eq!(curve.sample(1.0), 2.0);
+
+        assert_eq!(Curve::constant(3.0).sample(0.5), 3.0);
+        assert_eq!(
+            Curve::linear([0.0, 1.0], [1.0, 0.0]).sample(0.25),
+            [0.25, 0.75]
+        );
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        // A jump at 0.5, the last key given for a time wins from that time on
+        let curve = Curve::new(vec![(0.0, 0.0), (0.5, 1.0), (0.5, 3.0), (1.0, 4.0)]);
+        assert_eq!(curve.sample(0.25), 0.5);
+        assert_eq!(curve.sample(0.5), 3.0);
+        assert_eq!(curve.sample(0.75), 3.5);
+    }
+
+    #[test]
+    fn bake() {
+        let curve = Curve::new(vec![(0.0, 0.0), (0.5, 1.0), (1.0, 0.0)]);
+        assert_eq!(curve.bake::<5>(), [0.0, 0.5, 1.0, 0.5, 0.0]);
+    }
+}
@@ -0,0 +1,319 @@
+use std::ops::Range;
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, Transform as _, Vector3};
+
+use crate::{model::ModelVertex, node::Transform};
+
+use super::{curve::Lerp, Particle};
+
+/// Triangles particles are spawned on, picked by their area
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshSurface {
+    triangles: Vec<[Point3<f32>; 3]>,
+    // Sum of the areas up to each triangle (included)
+    areas: Vec<f32>,
+}
+
+impl MeshSurface {
+    pub fn new(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|triangle| std::array::from_fn(|i| Point3::from(positions[triangle[i] as usize])))
+            .collect::<Vec<_>>();
+        let areas = triangles
+            .iter()
+            .scan(0.0, |total, [a, b, c]| {
+                *total += (b - a).cross(c - a).magnitude() / 2.0;
+                Some(*total)
+            })
+            .collect();
+        Self { triangles, areas }
+    }
+
+    /// The surface of a mesh as the primitives generate it (see `primitives`)
+    pub fn from_vertices(vertices: &[ModelVertex], indices: &[u32]) -> Self {
+        let positions = vertices
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect::<Vec<_>>();
+        Self::new(&positions, indices)
+    }
+
+    // A point and the normal of its triangle
+    fn sample(&self, rng: &mut Rng) -> (Point3<f32>, Vector3<f32>) {
+        let Some(&total) = self.areas.last() else {
+            return (Point3::origin(), Vector3::unit_y());
+        };
+        let area = rng.next() * total;
+        let index = self
+            .areas
+            .partition_point(|&sum| sum < area)
+            .min(self.triangles.len() - 1);
+        let [a, b, c] = self.triangles[index];
+
+        // Folded back into the triangle when past its diagonal
+        let (mut u, mut v) = (rng.next(), rng.next());
+        if u + v > 1.0 {
+            (u, v) = (1.0 - u, 1.0 - v);
+        }
+        let normal = (b - a).cross(c - a);
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            Vector3::unit_y()
+        };
+        (a + (b - a) * u + (c - a) * v, normal)
+    }
+}
+
+/// Where particles are born, centered on the emitter, and the direction they leave in
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmitterShape {
+    // In every direction
+    Point,
+    // Anywhere inside, away from the center
+    Sphere { radius: f32 },
+    // On a disc facing +Y, within `angle` degrees of +Y
+    Cone { radius: f32, angle: f32 },
+    // Anywhere inside, towards +Y
+    Box { half_extents: [f32; 3] },
+    // On the triangles, along their normal
+    Mesh(MeshSurface),
+}
+
+impl EmitterShape {
+    fn sample(&self, rng: &mut Rng) -> (Point3<f32>, Vector3<f32>) {
+        match self {
+            EmitterShape::Point => (Point3::origin(), rng.direction()),
+            EmitterShape::Sphere { radius } => {
+                let direction = rng.direction();
+                // The cube root spreads the points evenly in the volume
+                let distance = radius * rng.next().cbrt();
+                (Point3::from_vec(direction * distance), direction)
+            }
+            EmitterShape::Cone { radius, angle } => {
+                let (sin, cos) = (rng.next() * std::f32::consts::TAU).sin_cos();
+                let distance = radius * rng.next().sqrt();
+                let position = Point3::new(cos * distance, 0.0, sin * distance);
+
+                // Uniform over the cap of the sphere the cone cuts
+                let (sin, cos) = (rng.next() * std::f32::consts::TAU).sin_cos();
+                let height = 1.0_f32.lerp(angle.to_radians().cos(), rng.next());
+                let spread = (1.0 - height * height).max(0.0).sqrt();
+                (position, Vector3::new(cos * spread, height, sin * spread))
+            }
+            EmitterShape::Box { half_extents } => {
+                let [x, y, z] = half_extents.map(|extent| extent * (rng.next() * 2.0 - 1.0));
+                (Point3::new(x, y, z), Vector3::unit_y())
+            }
+            EmitterShape::Mesh(surface) => surface.sample(rng),
+        }
+    }
+}
+
+/// A number of particles spawned at once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Burst {
+    /// Seconds since the creation of the system
+    pub time: f32,
+    pub count: u32,
+    /// Seconds between repetitions, the burst happens once without it
+    pub interval: Option<f32>,
+}
+
+/// How particles are spawned, each value picked between the bounds of its range
+#[derive(Clone, Debug)]
+pub struct ParticleEmitter {
+    // Relative to the node the system is attached to (or to the world)
+    pub transform: Transform,
+    pub shape: EmitterShape,
+    // Particles per second, on top of the bursts
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    // Along the direction of the shape
+    pub speed: Range<f32>,
+    // Added to the velocity, in world space
+    pub velocity: Range<[f32; 3]>,
+    /// In seconds
+    pub lifetime: Range<f32>,
+}
+
+impl Default for ParticleEmitter {
+    fn default() -> Self {
+        Self {
+            transform: Transform::default(),
+            shape: EmitterShape::Point,
+            rate: 10.0,
+            bursts: Vec::new(),
+            speed: 1.0..2.0,
+            velocity: [0.0; 3]..[0.0; 3],
+            lifetime: 1.5..2.5,
+        }
+    }
+}
+
+/// What an emitter did so far, kept by its system
+#[derive(Clone, Debug, Default)]
+pub(super) struct EmitterState {
+    rng: Rng,
+    // Part of a particle the rate spawned, carried to the next update
+    pending: f32,
+    // When each burst happens next
+    next_bursts: Vec<f32>,
+    // Particles due before there was room for them, spawned as soon as some die
+    waiting: u32,
+}
+
+impl EmitterState {
+    /// Number of particles to spawn for the `delta` seconds from `time` (since the creation
+    /// of the system), bursts count at the start (so the ones at 0 aren't missed)
+    /// The ones left by `wait` come first
+    pub fn advance(&mut self, emitter: &ParticleEmitter, time: f32, delta: f32) -> u32 {
+        if self.next_bursts.len() != emitter.bursts.len() {
+            self.next_bursts = emitter.bursts.iter().map(|burst| burst.time).collect();
+        }
+
+        let mut count = std::mem::take(&mut self.waiting);
+        for (burst, next) in emitter.bursts.iter().zip(&mut self.next_bursts) {
+            while *next <= time {
+                count += burst.count;
+                *next = match burst.interval {
+                    Some(interval) if interval > 0.0 => *next + interval,
+                    _ => f32::INFINITY,
+                };
+            }
+        }
+
+        self.pending += emitter.rate.max(0.0) * delta;
+        let spawned = self.pending.floor();
+        self.pending -= spawned;
+        count + spawned as u32
+    }
+
+    /// Keeps the particles that found no dead slot for the next `advance`,
+    /// at most `capacity` so a system that stays full doesn't pile them up
+    pub fn wait(&mut self, count: u32, capacity: u32) {
+        self.waiting = count.min(capacity);
+    }
+
+    /// A new particle, `model` places the emitter in the world
+    pub fn spawn(&mut self, emitter: &ParticleEmitter, model: &Matrix4<f32>) -> Particle {
+        let rng = &mut self.rng;
+        let (position, direction) = emitter.shape.sample(rng);
+        let direction = model.transform_vector(direction);
+        let direction = if direction.magnitude2() > 0.0 {
+            direction.normalize()
+        } else {
+            direction
+        };
+        let speed = emitter.speed.start.lerp(emitter.speed.end, rng.next());
+        let velocity: [f32; 3] = std::array::from_fn(|i| {
+            let amount = rng.next();
+            emitter.velocity.start[i].lerp(emitter.velocity.end[i], amount)
+        });
+
+        Particle {
+            position: model.transform_point(position).into(),
+            age: 0.0,
+            velocity: (direction * speed + Vector3::from(velocity)).into(),
+            lifetime: emitter
+                .lifetime
+                .start
+                .lerp(emitter.lifetime.end, rng.next()),
+        }
+    }
+}
+
+// Xorshift, the same sequence every run so scenes are reproducible
+#[derive(Clone, Debug)]
+struct Rng(u32);
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self(0x9E37_79B9)
+    }
+}
+
+impl Rng {
+    // From 0 (included) to 1 (excluded)
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+
+    // Uniform on the unit sphere
+    fn direction(&mut self) -> Vector3<f32> {
+        let (sin, cos) = (self.next() * std::f32::consts::TAU).sin_cos();
+        let height = self.next() * 2.0 - 1.0;
+        let spread = (1.0 - height * height).sqrt();
+        Vector3::new(cos * spread, height, sin * spread)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitter(rate: f32, bursts: Vec<Burst>) -> ParticleEmitter {
+        ParticleEmitter {
+            rate,
+            bursts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn burst_at_start() {
+        let emitter = emitter(
+            0.0,
+            vec![Burst {
+                time: 0.0,
+                count: 5,
+                interval: None,
+            }],
+        );
+        let mut state = EmitterState::default();
+        // Even when the first update doesn't advance the time
+        assert_eq!(state.advance(&emitter, 0.0, 0.0), 5);
+        assert_eq!(state.advance(&emitter, 0.0, 0.1), 0);
+        assert_eq!(state.advance(&emitter, 0.1, 10.0), 0);
+    }
+
+    #[test]
+    fn repeated_bursts() {
+        let emitter = emitter(
+            0.0,
+            vec![Burst {
+                time: 0.0,
+                count: 2,
+                interval: Some(0.1),
+            }],
+        );
+        let mut state = EmitterState::default();
+        assert_eq!(state.advance(&emitter, 0.0, 0.35), 2);
+        // A long frame catches up with every repetition it skipped (at 0.1, 0.2 and 0.3)
+        assert_eq!(state.advance(&emitter, 0.35, 0.05), 6);
+        assert_eq!(state.advance(&emitter, 0.4, 0.05), 2);
+    }
+
+    #[test]
+    fn rate_remainder() {
+        let emitter = emitter(6.0, Vec::new());
+        let mut state = EmitterState::default();
+        // 1.5 particles, then 1.5 + 0.5 left over
+        assert_eq!(state.advance(&emitter, 0.0, 0.25), 1);
+        assert_eq!(state.advance(&emitter, 0.25, 0.25), 2);
+        assert_eq!(state.advance(&emitter, 0.5, 0.1), 0);
+    }
+
+    #[test]
+    fn waiting() {
+        let emitter = emitter(0.0, Vec::new());
+        let mut state = EmitterState::default();
+        state.wait(5, 3);
+        assert_eq!(state.advance(&emitter, 0.0, 0.1), 3);
+        assert_eq!(state.advance(&emitter, 0.1, 0.1), 0);
+    }
+}
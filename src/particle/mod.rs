@@ -2,55 +2,158 @@ use instant::Duration;
 
 use std::sync::atomic::AtomicU32;
 
-use crate::{instance::Instance, model, pass::phong::Locals};
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
 
-pub struct ParticleSystem {
-    // Local position of model (for relative calculations)
-    pub locals: Locals,
-    // The vertex buffers and texture data
-    pub model: model::Model,
-    // An array of positional data for each instance (can just pass 1 instance)
-    pub particle_data: Vec<Particle>,
-
-    buffer: wgpu::Buffer,
+use crate::{
+    node::{NodeId, Nodes},
+    texture,
+};
+
+use self::emitter::EmitterState;
+pub use self::{
+    curve::{Curve, Lerp},
+    emitter::{Burst, EmitterShape, MeshSurface, ParticleEmitter},
+    simulation::{ParticleForces, ParticleSimulation},
+};
+
+mod curve;
+mod emitter;
+mod simulation;
+
+/// How particles are blended over the scene
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParticleBlend {
+    // Adds up, for particles giving off light (fire, sparks)
+    #[default]
+    Additive,
+    // Covers what is behind (smoke, dust)
+    // Particles aren't sorted, overlapping ones may blend in the wrong order
+    Alpha,
+}
+
+/// Look of the particles over their life
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleAppearance {
+    pub blend: ParticleBlend,
+    // Width and height of the quads, in world units
+    pub size: Curve<f32>,
+    // Linear colors, multiplied with the texture (the alpha fades particles out)
+    pub color: Curve<[f32; 4]>,
+}
+
+impl Default for ParticleAppearance {
+    fn default() -> Self {
+        Self {
+            blend: ParticleBlend::default(),
+            size: Curve::linear(0.2, 0.05),
+            color: Curve::linear([1.0, 0.8, 0.4, 1.0], [1.0, 0.2, 0.0, 0.0]),
+        }
+    }
 }
 
+// `Appearance` in `shaders/particle.wgsl`, the curves baked into `CURVE_SAMPLES` samples
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AppearanceUniform {
+    colors: [[f32; 4]; ParticleSystem::CURVE_SAMPLES],
+    // Four per vector
+    sizes: [[f32; 4]; ParticleSystem::CURVE_SAMPLES / 4],
+}
+
+// What a system needs to be moved by `ParticleSimulation`
+struct GpuSimulation {
+    uniform_buffer: wgpu::Buffer,
+    // Reading `buffers[i]`, writing the other one
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+pub struct ParticleSystem {
+    // The emitter follows this node, particles stay where they were spawned (in world space)
+    pub parent: Option<NodeId>,
+    pub emitter: ParticleEmitter,
+    pub appearance: ParticleAppearance,
+    pub forces: ParticleForces,
+    // The state of the particles when they are moved on the CPU,
+    // otherwise only their age is kept up to date (to know which ones are dead)
+    particles: Vec<Particle>,
+    emitter_state: EmitterState,
+    // Seconds since the creation of the system, the bursts and the noise depend on it
+    time: f32,
+
+    // The last state is in `buffers[current]`, the GPU simulation writes the next one
+    // in the other buffer (there is a single buffer without it)
+    buffers: Vec<wgpu::Buffer>,
+    current: usize,
+    gpu: Option<GpuSimulation>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // Kept alive for the bind group
+    _texture: texture::Texture,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Default)]
 pub struct Particle {
     pub position: [f32; 3],
-    padding_0: f32,
-    // pub velocity: [f32; 3],
-    /// The lifetime of the particle in milliseconds
+    /// In seconds, the particle is dead (and not drawn) once past its lifetime
+    pub age: f32,
+    pub velocity: [f32; 3],
     pub lifetime: f32,
-    padding_1: [f32; 3],
 }
 
-impl Default for Particle {
-    fn default() -> Self {
-        Self {
-            position: [0f32; 3],
-            padding_0: 0f32,
-            lifetime: 0f32,
-            padding_1: [0f32; 3],
+impl Particle {
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            // One quad per particle
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
         }
     }
 }
 
 #[cfg(debug_assertions)]
-const PARTICLE_SYSTEM_ID: AtomicU32 = AtomicU32::new(0);
+static PARTICLE_SYSTEM_ID: AtomicU32 = AtomicU32::new(0);
 
 impl ParticleSystem {
-    pub fn new(device: &wgpu::Device, model: model::Model, locals: Locals, count: u32) -> Self {
-        use wgpu::util::DeviceExt;
-
-        let particle_data = (0..count)
-            .map(|i| Particle {
-                position: [i as f32; 3],
-                lifetime: 2f32,
-                ..Default::default()
-            })
-            .collect::<Vec<_>>();
+    // Side of the default texture
+    const SPRITE_SIZE: u32 = 32;
+    // Samples of the appearance curves, `CURVE_SAMPLES` in `shaders/particle.wgsl`
+    const CURVE_SAMPLES: usize = 16;
+
+    /// At most `max_particles` are alive at once, past that the emitter waits for some to die
+    /// Without `texture`, particles are soft white discs
+    /// Without `simulation`, particles are moved on the CPU
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation: Option<&ParticleSimulation>,
+        texture: Option<texture::Texture>,
+        emitter: ParticleEmitter,
+        appearance: ParticleAppearance,
+        forces: ParticleForces,
+        max_particles: u32,
+    ) -> Self {
+        // Dead until spawned
+        let particles = vec![Particle::default(); max_particles as usize];
 
         #[cfg(debug_assertions)]
         let label = &format!(
@@ -60,30 +163,264 @@ impl ParticleSystem {
         #[cfg(not(debug_assertions))]
         let label = "Particle Instance Buffer";
 
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(label),
-            contents: bytemuck::cast_slice(&particle_data),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        // Empty buffers can't be bound, nothing to simulate anyway
+        let simulation = simulation.filter(|_| max_particles > 0);
+        let usage = match simulation {
+            Some(_) => wgpu::BufferUsages::STORAGE,
+            None => wgpu::BufferUsages::empty(),
+        };
+        let buffers = (0..if simulation.is_some() { 2 } else { 1 })
+            .map(|_| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents: bytemuck::cast_slice(&particles),
+                    usage: usage | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                })
+            })
+            .collect::<Vec<_>>();
+        let gpu = simulation.map(|simulation| {
+            let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("[Particles] Simulation"),
+                size: std::mem::size_of::<simulation::SimulationUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = |source: &wgpu::Buffer, destination: &wgpu::Buffer| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("[Particles] Simulation bind group"),
+                    layout: &simulation.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: source.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: destination.as_entire_binding(),
+                        },
+                    ],
+                })
+            };
+            GpuSimulation {
+                bind_groups: [
+                    bind_group(&buffers[0], &buffers[1]),
+                    bind_group(&buffers[1], &buffers[0]),
+                ],
+                uniform_buffer,
+            }
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("[Particles] Appearance"),
+            contents: bytemuck::cast_slice(&[Self::uniform(&appearance)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let texture = texture.unwrap_or_else(|| Self::default_sprite(device, queue));
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("[Particles] Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("[Particles] Bind group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
         });
 
         Self {
-            locals,
-            model,
-            particle_data,
-            buffer,
+            parent: None,
+            emitter,
+            appearance,
+            forces,
+            particles,
+            emitter_state: EmitterState::default(),
+            time: 0.0,
+            buffers,
+            current: 0,
+            gpu,
+            uniform_buffer,
+            bind_group,
+            _texture: texture,
         }
     }
 
-    pub fn update(&mut self, delta: Duration, queue: &wgpu::Queue) {
-        for particle in &mut self.particle_data {
-            particle.lifetime = match particle.lifetime {
-                life if life < -5f32 => 2f32,
-                life => life - delta.as_millis() as f32,
-            };
+    /// Layout of the bind group of each system (group 1 of `shaders/particle.wgsl`)
+    /// wgpu deduplicates layouts, the one of the pipelines is created the same way
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("[Particles] Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    // White disc fading out towards its edge
+    fn default_sprite(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+        let size = Self::SPRITE_SIZE;
+        let image = image::RgbaImage::from_fn(size, size, |x, y| {
+            let center = size as f32 / 2.0;
+            let distance = ((x as f32 + 0.5 - center).powi(2) + (y as f32 + 0.5 - center).powi(2))
+                .sqrt()
+                / center;
+            let alpha = (1.0 - distance).clamp(0.0, 1.0).powi(2);
+            image::Rgba([255, 255, 255, (alpha * 255.0) as u8])
+        });
+        texture::Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(image),
+            Some("[Particles] Sprite"),
+        )
+        .expect("The sprite is a valid image")
+    }
+
+    fn uniform(appearance: &ParticleAppearance) -> AppearanceUniform {
+        let sizes: [f32; ParticleSystem::CURVE_SAMPLES] = appearance.size.bake();
+        AppearanceUniform {
+            colors: appearance.color.bake(),
+            sizes: std::array::from_fn(|i| std::array::from_fn(|j| sizes[i * 4 + j])),
+        }
+    }
+
+    /// Spawns particles in place of dead ones then moves them all,
+    /// on the GPU when the system was created with a `ParticleSimulation` (the same one)
+    /// `nodes` must have their world matrices up to date, for the emitter to follow its parent
+    pub fn update(
+        &mut self,
+        delta: Duration,
+        nodes: &Nodes,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation: Option<&ParticleSimulation>,
+    ) {
+        let delta = delta.as_secs_f32();
+        let parent = self
+            .parent
+            .and_then(|id| nodes.get(id))
+            .map_or_else(Matrix4::identity, |node| node.world_matrix());
+        let model = parent * self.emitter.transform.to_matrix();
+        let mut to_spawn = self.emitter_state.advance(&self.emitter, self.time, delta);
+
+        let gpu = self.gpu.as_ref().zip(simulation);
+        let mut spawned = Vec::new();
+        for (index, particle) in self.particles.iter_mut().enumerate() {
+            if particle.age >= particle.lifetime {
+                if to_spawn == 0 {
+                    continue;
+                }
+                *particle = self.emitter_state.spawn(&self.emitter, &model);
+                spawned.push((index, *particle));
+                to_spawn -= 1;
+            }
+            if gpu.is_some() {
+                particle.age += delta;
+            } else {
+                self.forces.integrate(particle, delta, self.time);
+            }
         }
 
-        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.particle_data));
+        self.emitter_state
+            .wait(to_spawn, self.particles.len() as u32);
+
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Self::uniform(&self.appearance)]),
+        );
+        let Some((gpu, simulation)) = gpu else {
+            queue.write_buffer(&self.buffers[0], 0, bytemuck::cast_slice(&self.particles));
+            self.time += delta;
+            return;
+        };
+
+        // Only the spawned particles come from the CPU
+        let stride = std::mem::size_of::<Particle>() as wgpu::BufferAddress;
+        for (index, particle) in spawned {
+            queue.write_buffer(
+                &self.buffers[self.current],
+                index as wgpu::BufferAddress * stride,
+                bytemuck::cast_slice(&[particle]),
+            );
+        }
+        let count = self.particles.len() as u32;
+        queue.write_buffer(
+            &gpu.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.forces.uniform(delta, self.time, count)]),
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("[Particles] Simulation encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("[Particles] Simulation"),
+            });
+            compute_pass.set_pipeline(&simulation.pipeline);
+            compute_pass.set_bind_group(0, &gpu.bind_groups[self.current], &[]);
+            compute_pass.dispatch_workgroups(
+                count.div_ceil(ParticleSimulation::WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+        queue.submit(Some(encoder.finish()));
+        self.current = 1 - self.current;
+        self.time += delta;
     }
 
-    fn draw(&mut self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {}
+    /// Draws the live particles, the pipeline of `self.appearance.blend`
+    /// and the camera (bind group 0) must already be set (see `ParticleRenderer`)
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.particles.is_empty() {
+            return;
+        }
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffers[self.current].slice(..));
+        render_pass.draw(0..4, 0..self.particles.len() as u32);
+    }
 }
@@ -0,0 +1,144 @@
+use cgmath::Vector3;
+use wgpu::Device;
+
+use super::Particle;
+
+/// Forces moving the particles, the same for every particle of a system
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParticleForces {
+    // Acceleration, in world units per second squared
+    pub gravity: [f32; 3],
+    // Share of the velocity lost each second
+    pub drag: f32,
+    // Acceleration towards a direction varying smoothly with the position and time
+    pub noise: f32,
+    // How fast the direction of the noise varies in space
+    pub noise_frequency: f32,
+}
+
+impl Default for ParticleForces {
+    fn default() -> Self {
+        Self {
+            gravity: [0.0, -9.81, 0.0],
+            drag: 0.1,
+            noise: 0.0,
+            noise_frequency: 1.0,
+        }
+    }
+}
+
+impl ParticleForces {
+    /// Advances a live particle by `delta` seconds (semi-implicit Euler),
+    /// same as `cs_main` in `shaders/particle_simulation.wgsl`
+    pub fn integrate(&self, particle: &mut Particle, delta: f32, time: f32) {
+        let position = Vector3::from(particle.position);
+        let velocity = Vector3::from(particle.velocity);
+        let noise = Vector3::new(
+            (position.y * self.noise_frequency + time).sin(),
+            (position.z * self.noise_frequency + time * 1.3).sin(),
+            (position.x * self.noise_frequency + time * 0.7).sin(),
+        );
+        let acceleration =
+            Vector3::from(self.gravity) + noise * self.noise - velocity * self.clamped_drag(delta);
+
+        let velocity = velocity + acceleration * delta;
+        particle.velocity = velocity.into();
+        particle.position = (position + velocity * delta).into();
+        particle.age += delta;
+    }
+
+    // Drag above 1 / delta would reverse the velocity
+    fn clamped_drag(&self, delta: f32) -> f32 {
+        if delta > 0.0 {
+            self.drag.min(1.0 / delta)
+        } else {
+            self.drag
+        }
+    }
+
+    pub(super) fn uniform(&self, delta: f32, time: f32, count: u32) -> SimulationUniform {
+        SimulationUniform {
+            gravity_drag: [
+                self.gravity[0],
+                self.gravity[1],
+                self.gravity[2],
+                self.clamped_drag(delta),
+            ],
+            noise_time: [self.noise, self.noise_frequency, delta, time],
+            count: [count, 0, 0, 0],
+        }
+    }
+}
+
+// `Simulation` in `shaders/particle_simulation.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub(super) struct SimulationUniform {
+    gravity_drag: [f32; 4],
+    // Noise strength and frequency, delta and time (in seconds)
+    noise_time: [f32; 4],
+    // Number of particles, then padding
+    count: [u32; 4],
+}
+
+/// Compute pipeline moving the particles on the GPU, shared by every system
+/// Each system reads its particles from one storage buffer and writes them to the other,
+/// then the two are swapped (see `ParticleSystem::update`)
+/// Not available on WebGL (no compute shaders), particles are moved on the CPU instead
+pub struct ParticleSimulation {
+    pub(super) layout: wgpu::BindGroupLayout,
+    pub(super) pipeline: wgpu::ComputePipeline,
+}
+
+impl ParticleSimulation {
+    pub const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(device: &Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("[Particles] Simulation layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("[Particles] Simulation shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../shaders/particle_simulation.wgsl").into(),
+            ),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("[Particles] Simulation pipeline"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("[Particles] Simulation pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        Self { layout, pipeline }
+    }
+}
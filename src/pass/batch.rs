@@ -0,0 +1,401 @@
+use std::{collections::HashMap, ops::Range};
+
+use wgpu::{util::DrawIndexedIndirect, Device, Queue};
+
+use crate::{
+    instance::InstanceBuffer,
+    model::{AlphaMode, ModelVertex},
+    node::{NodeId, Nodes},
+};
+
+use super::{culling::Culling, MaterialPipelines, NodeResources};
+
+/// How the opaque meshes are drawn, from what the device supports
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawMode {
+    // One `draw_indexed` per mesh, from its own buffers
+    // (WebGL has neither indirect draws nor base vertices)
+    Direct,
+    // Meshes are gathered in a `MeshArena`, the arguments of the draws in an indirect buffer
+    Indirect {
+        // Consecutive draws sharing their bind groups are issued at once
+        multi_draw: bool,
+        // Indirect draws can start past the first instance, otherwise the instance buffer is
+        // bound from the first one drawn
+        first_instance: bool,
+    },
+}
+
+impl DrawMode {
+    /// `indirect` when the adapter can draw indirectly (see `GraphicsContext::supported_indirect_draws`)
+    pub fn new(indirect: bool, device: &Device) -> Self {
+        if !indirect {
+            return DrawMode::Direct;
+        }
+        let features = device.features();
+        DrawMode::Indirect {
+            multi_draw: features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+            first_instance: features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE),
+        }
+    }
+}
+
+// Where a mesh starts in the arena
+#[derive(Clone, Copy)]
+struct MeshSlice {
+    base_vertex: i32,
+    first_index: u32,
+}
+
+/// The vertices and indices of every mesh of the scene, packed in two buffers so the
+/// draws don't have to switch buffers between meshes
+/// Meshes are copied on the GPU from their own buffers. Removed ones leave holes,
+/// reclaimed when the arena grows (everything still alive is copied again)
+pub struct MeshArena {
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    vertex_capacity: u32,
+    index_capacity: u32,
+    vertex_count: u32,
+    index_count: u32,
+    slices: HashMap<NodeId, Vec<MeshSlice>>,
+}
+
+impl MeshArena {
+    const VERTEX_STRIDE: wgpu::BufferAddress =
+        std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress;
+    const INDEX_STRIDE: wgpu::BufferAddress = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+    pub fn new(device: &Device) -> Self {
+        Self::with_capacity(device, 1024, 4096)
+    }
+
+    fn with_capacity(device: &Device, vertex_capacity: u32, index_capacity: u32) -> Self {
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        Self {
+            vertices: buffer(
+                "[Arena] Vertices",
+                vertex_capacity as wgpu::BufferAddress * Self::VERTEX_STRIDE,
+                wgpu::BufferUsages::VERTEX,
+            ),
+            indices: buffer(
+                "[Arena] Indices",
+                index_capacity as wgpu::BufferAddress * Self::INDEX_STRIDE,
+                wgpu::BufferUsages::INDEX,
+            ),
+            vertex_capacity,
+            index_capacity,
+            vertex_count: 0,
+            index_count: 0,
+            slices: HashMap::new(),
+        }
+    }
+
+    /// Copies the meshes of new nodes and forgets the removed ones
+    pub fn update(&mut self, nodes: &Nodes, device: &Device, queue: &Queue) {
+        self.slices.retain(|id, _| nodes.contains(*id));
+        let new_nodes = nodes
+            .ids()
+            .filter(|id| !self.slices.contains_key(id))
+            .collect::<Vec<_>>();
+        if new_nodes.is_empty() {
+            return;
+        }
+
+        let size = |ids: &mut dyn Iterator<Item = NodeId>| {
+            ids.filter_map(|id| nodes.get(id))
+                .flat_map(|node| &node.model.meshes)
+                .fold((0, 0), |(vertices, indices), mesh| {
+                    (vertices + mesh.num_vertices, indices + mesh.num_elements)
+                })
+        };
+        let (vertices, indices) = size(&mut new_nodes.iter().copied());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("[Arena] Encoder"),
+        });
+        if self.vertex_count + vertices > self.vertex_capacity
+            || self.index_count + indices > self.index_capacity
+        {
+            // Everything is copied again, without the holes of the removed nodes
+            let (vertices, indices) = size(&mut nodes.ids());
+            *self = Self::with_capacity(
+                device,
+                vertices.next_power_of_two().max(self.vertex_capacity),
+                indices.next_power_of_two().max(self.index_capacity),
+            );
+            for id in nodes.ids() {
+                self.copy(id, nodes, &mut encoder);
+            }
+        } else {
+            for id in new_nodes {
+                self.copy(id, nodes, &mut encoder);
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    fn copy(&mut self, id: NodeId, nodes: &Nodes, encoder: &mut wgpu::CommandEncoder) {
+        let Some(node) = nodes.get(id) else {
+            return;
+        };
+        let mut slices = Vec::with_capacity(node.model.meshes.len());
+        for mesh in &node.model.meshes {
+            slices.push(MeshSlice {
+                base_vertex: self.vertex_count as i32,
+                first_index: self.index_count,
+            });
+            encoder.copy_buffer_to_buffer(
+                &mesh.vertex_buffer,
+                0,
+                &self.vertices,
+                self.vertex_count as wgpu::BufferAddress * Self::VERTEX_STRIDE,
+                mesh.num_vertices as wgpu::BufferAddress * Self::VERTEX_STRIDE,
+            );
+            encoder.copy_buffer_to_buffer(
+                &mesh.index_buffer,
+                0,
+                &self.indices,
+                self.index_count as wgpu::BufferAddress * Self::INDEX_STRIDE,
+                mesh.num_elements as wgpu::BufferAddress * Self::INDEX_STRIDE,
+            );
+            self.vertex_count += mesh.num_vertices;
+            self.index_count += mesh.num_elements;
+        }
+        self.slices.insert(id, slices);
+    }
+}
+
+// Draws sharing their pipeline, bind group and instance buffer binding
+struct Batch {
+    node: NodeId,
+    mesh: usize,
+    material: usize,
+    double_sided: bool,
+    // Where the instance buffer is bound from
+    first_bound_instance: u32,
+    // Of the draws in `DrawBatches::draws`
+    draws: Range<usize>,
+}
+
+/// The draws of the opaque meshes, grouped by pipeline then by node and material so that
+/// the state changes between them are kept to a minimum
+/// Built each frame from what the camera sees (after `Culling::cull`)
+pub struct DrawBatches {
+    mode: DrawMode,
+    arena: Option<MeshArena>,
+    batches: Vec<Batch>,
+    draws: Vec<DrawIndexedIndirect>,
+    indirect_buffer: Option<wgpu::Buffer>,
+    // Number of draws the indirect buffer can hold
+    indirect_capacity: usize,
+}
+
+impl DrawBatches {
+    const INDIRECT_STRIDE: wgpu::BufferAddress =
+        std::mem::size_of::<DrawIndexedIndirect>() as wgpu::BufferAddress;
+
+    pub fn new(mode: DrawMode, device: &Device) -> Self {
+        Self {
+            mode,
+            arena: match mode {
+                DrawMode::Direct => None,
+                DrawMode::Indirect { .. } => Some(MeshArena::new(device)),
+            },
+            batches: Vec::new(),
+            draws: Vec::new(),
+            indirect_buffer: None,
+            indirect_capacity: 0,
+        }
+    }
+
+    pub fn mode(&self) -> DrawMode {
+        self.mode
+    }
+
+    /// Gathers the visible opaque meshes of `nodes` and uploads the indirect draws
+    pub fn update(&mut self, nodes: &Nodes, culling: &Culling, device: &Device, queue: &Queue) {
+        if let Some(arena) = &mut self.arena {
+            arena.update(nodes, device, queue);
+        }
+        // Direct draws can always start past the first instance
+        let first_instance = !matches!(
+            self.mode,
+            DrawMode::Indirect {
+                first_instance: false,
+                ..
+            }
+        );
+
+        let mut draws = Vec::new();
+        for (id, node) in nodes.iter() {
+            let Some(visible) = culling.visible(id) else {
+                continue;
+            };
+            let slices = self.arena.as_ref().and_then(|arena| arena.slices.get(&id));
+            for ((mesh_index, mesh), ranges) in node.model.meshes.iter().enumerate().zip(visible) {
+                // The loaders give every mesh a material (see `Model::add_default_material`)
+                let Some(material) = node.model.materials.get(mesh.material) else {
+                    continue;
+                };
+                if material.alpha_mode == AlphaMode::Blend {
+                    continue;
+                }
+                let slice = slices.map_or(
+                    MeshSlice {
+                        base_vertex: 0,
+                        first_index: 0,
+                    },
+                    |slices| slices[mesh_index],
+                );
+                for instances in ranges {
+                    let first_bound_instance = if first_instance { 0 } else { instances.start };
+                    draws.push((
+                        Batch {
+                            node: id,
+                            mesh: mesh_index,
+                            material: mesh.material,
+                            double_sided: material.double_sided,
+                            first_bound_instance,
+                            draws: 0..0,
+                        },
+                        DrawIndexedIndirect {
+                            vertex_count: mesh.num_elements,
+                            instance_count: instances.len() as u32,
+                            base_index: slice.first_index,
+                            vertex_offset: slice.base_vertex,
+                            base_instance: instances.start - first_bound_instance,
+                        },
+                    ));
+                }
+            }
+        }
+        // Each node has its own bind groups (its locals), so draws can only be merged
+        // within a node. Stable, meshes keep their order within a batch
+        draws.sort_by_key(|(batch, _)| (batch.double_sided, batch.node, batch.material));
+
+        self.batches.clear();
+        self.draws.clear();
+        let indirect = self.arena.is_some();
+        for (batch, draw) in draws {
+            let index = self.draws.len();
+            self.draws.push(draw);
+            // Without the arena, each mesh has its own buffers
+            match self.batches.last_mut() {
+                Some(last)
+                    if last.node == batch.node
+                        && last.material == batch.material
+                        && last.double_sided == batch.double_sided
+                        && last.first_bound_instance == batch.first_bound_instance
+                        && (indirect || last.mesh == batch.mesh) =>
+                {
+                    last.draws.end = index + 1
+                }
+                _ => self.batches.push(Batch {
+                    draws: index..index + 1,
+                    ..batch
+                }),
+            }
+        }
+
+        if self.arena.is_none() || self.draws.is_empty() {
+            return;
+        }
+        if self.draws.len() > self.indirect_capacity {
+            self.indirect_capacity = self.draws.len().next_power_of_two();
+            self.indirect_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("[Batches] Indirect draws"),
+                size: self.indirect_capacity as wgpu::BufferAddress * Self::INDIRECT_STRIDE,
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let bytes = self
+            .draws
+            .iter()
+            .flat_map(|draw| draw.as_bytes())
+            .copied()
+            .collect::<Vec<_>>();
+        if let Some(buffer) = &self.indirect_buffer {
+            queue.write_buffer(buffer, 0, &bytes);
+        }
+    }
+
+    /// Draws the batches of the last `update`,
+    /// the global bind group must already be set on `render_pass`
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        nodes: &'a Nodes,
+        resources: &'a NodeResources,
+        pipelines: &'a MaterialPipelines,
+    ) {
+        if let Some(arena) = &self.arena {
+            render_pass.set_vertex_buffer(0, arena.vertices.slice(..));
+            render_pass.set_index_buffer(arena.indices.slice(..), wgpu::IndexFormat::Uint32);
+        }
+
+        let mut double_sided = None;
+        for batch in &self.batches {
+            let (Some(node), Some((instance_buffer, bind_groups))) =
+                (nodes.get(batch.node), resources.get(batch.node))
+            else {
+                continue;
+            };
+            let mesh = &node.model.meshes[batch.mesh];
+            let (Some(material), Some(bind_group)) = (
+                node.model.materials.get(batch.material),
+                bind_groups.get(batch.material),
+            ) else {
+                continue;
+            };
+            if double_sided != Some(batch.double_sided) {
+                render_pass.set_pipeline(pipelines.get(material));
+                double_sided = Some(batch.double_sided);
+            }
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.set_vertex_buffer(
+                1,
+                instance_buffer.buffer.slice(
+                    batch.first_bound_instance as wgpu::BufferAddress * InstanceBuffer::STRIDE..,
+                ),
+            );
+
+            match (self.mode, &self.indirect_buffer) {
+                (DrawMode::Indirect { multi_draw, .. }, Some(indirect_buffer)) => {
+                    let offset = batch.draws.start as wgpu::BufferAddress * Self::INDIRECT_STRIDE;
+                    if multi_draw {
+                        render_pass.multi_draw_indexed_indirect(
+                            indirect_buffer,
+                            offset,
+                            batch.draws.len() as u32,
+                        );
+                    } else {
+                        for index in 0..batch.draws.len() as wgpu::BufferAddress {
+                            render_pass.draw_indexed_indirect(
+                                indirect_buffer,
+                                offset + index * Self::INDIRECT_STRIDE,
+                            );
+                        }
+                    }
+                }
+                _ => {
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass
+                        .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    for draw in &self.draws[batch.draws.clone()] {
+                        let instances =
+                            draw.base_instance..draw.base_instance + draw.instance_count;
+                        render_pass.draw_indexed(0..draw.vertex_count, 0, instances);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{
+    bounds::Frustum,
+    camera::CameraUniform,
+    node::{NodeId, Nodes},
+};
+
+/// How many meshes the last `Culling::cull` kept, each instance of a mesh counts as one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub nodes: usize,
+    // Nodes without a single visible mesh
+    pub culled_nodes: usize,
+    pub meshes: usize,
+    pub culled_meshes: usize,
+}
+
+/// The mesh instances inside the camera frustum, found before drawing
+/// Each instance of each mesh is tested with its bounding sphere first (cheaper),
+/// then with its box (tighter)
+pub struct Culling {
+    frustum: Frustum,
+    // Per node then mesh, consecutive visible instances are drawn at once
+    visible: HashMap<NodeId, Vec<Vec<Range<u32>>>>,
+    stats: CullingStats,
+}
+
+impl Culling {
+    pub fn new() -> Self {
+        Self {
+            frustum: CameraUniform::new().frustum(),
+            visible: HashMap::new(),
+            stats: CullingStats::default(),
+        }
+    }
+
+    pub fn set_camera(&mut self, camera_uniform: &CameraUniform) {
+        self.frustum = camera_uniform.frustum();
+    }
+
+    /// Finds the visible instances of `nodes`,
+    /// called each frame since both the camera and the nodes may have moved
+    pub fn cull(&mut self, nodes: &Nodes) {
+        self.visible.clear();
+        self.stats = CullingStats::default();
+        for (id, node) in nodes.iter() {
+            let world_matrix = node.world_matrix();
+            let instances = node
+                .instances()
+                .iter()
+                .map(|instance| world_matrix * instance.model_matrix())
+                .collect::<Vec<_>>();
+
+            let mut node_visible = false;
+            let meshes = node
+                .model
+                .meshes
+                .iter()
+                .map(|mesh| {
+                    let mut ranges: Vec<Range<u32>> = Vec::new();
+                    for (index, matrix) in instances.iter().enumerate() {
+                        let visible = self
+                            .frustum
+                            .intersects_sphere(&mesh.bounds.sphere.transform(matrix))
+                            && self
+                                .frustum
+                                .intersects_aabb(&mesh.bounds.aabb.transform(matrix));
+                        self.stats.meshes += 1;
+                        if !visible {
+                            self.stats.culled_meshes += 1;
+                            continue;
+                        }
+
+                        let index = index as u32;
+                        match ranges.last_mut() {
+                            Some(range) if range.end == index => range.end += 1,
+                            _ => ranges.push(index..index + 1),
+                        }
+                    }
+                    node_visible |= !ranges.is_empty();
+                    ranges
+                })
+                .collect();
+
+            self.stats.nodes += 1;
+            if !node_visible {
+                self.stats.culled_nodes += 1;
+            }
+            self.visible.insert(id, meshes);
+        }
+    }
+
+    /// The ranges of visible instances of each mesh of a node,
+    /// None for nodes added since the last `cull`
+    pub fn visible(&self, id: NodeId) -> Option<&[Vec<Range<u32>>]> {
+        self.visible.get(&id).map(Vec::as_slice)
+    }
+
+    pub fn stats(&self) -> CullingStats {
+        self.stats
+    }
+}
+
+impl Default for Culling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,283 @@
+use std::num::NonZeroU32;
+
+use wgpu::{util::DeviceExt, Device, Queue};
+
+use crate::{cubemap::FaceRenderer, post::PostChain, texture};
+
+/// Ambient light of the scene, from the environment cubemap (image based lighting)
+/// Follows the split sum approximation: the irradiance lights diffuse surfaces, the
+/// environment blurred by roughness (one mip level each) is reflected by specular ones,
+/// scaled and biased by a lookup table of the BRDF
+/// Without an environment the maps stay black and the shaders use the flat ambient color
+pub struct Ibl {
+    irradiance: texture::Texture,
+    prefiltered: texture::Texture,
+    brdf_lut: texture::Texture,
+    sampler: wgpu::Sampler,
+    environment: bool,
+}
+
+impl Ibl {
+    const IRRADIANCE_SIZE: u32 = 16;
+    // At most, smaller environments keep their size
+    const PREFILTERED_SIZE: u32 = 128;
+    // From a mirror (level 0) to a roughness of 1 (last level, see `PREFILTERED_MAX_LEVEL`
+    // in `shaders/lighting.wgsl`)
+    const PREFILTERED_LEVELS: u32 = 5;
+    const BRDF_LUT_SIZE: u32 = 64;
+
+    pub fn new(device: &Device, queue: &Queue, environment: Option<&texture::Texture>) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("[IBL] Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("[IBL] Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let faces = FaceRenderer::new(device);
+        let pipeline = |entry_point| {
+            faces.pipeline(
+                device,
+                &format!("[IBL] {}", entry_point),
+                include_str!("../shaders/ibl.wgsl"),
+                entry_point,
+                &layout,
+                PostChain::HDR_FORMAT,
+            )
+        };
+        let bind_group = |source: &wgpu::TextureView, roughness: f32| {
+            let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("[IBL] Prefilter"),
+                contents: bytemuck::cast_slice(&[roughness, 0.0, 0.0, 0.0]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("[IBL] Bind group"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("[IBL] Encoder"),
+        });
+
+        // The BRDF doesn't depend on the environment, the flat ambient color uses it too
+        let brdf_lut = texture::Texture::create_attachment(
+            device,
+            (Ibl::BRDF_LUT_SIZE, Ibl::BRDF_LUT_SIZE),
+            PostChain::HDR_FORMAT,
+            1,
+            usage,
+            "[IBL] BRDF LUT",
+        );
+        let placeholder =
+            texture::Texture::create_cube(device, 1, PostChain::HDR_FORMAT, 1, usage, "[IBL] None");
+        faces.draw(
+            &mut encoder,
+            &pipeline("fs_brdf"),
+            &bind_group(&placeholder.view, 0.0),
+            &brdf_lut.view,
+            0,
+        );
+
+        let Some(environment) = environment else {
+            queue.submit(Some(encoder.finish()));
+            let prefiltered = texture::Texture::create_cube(
+                device,
+                1,
+                PostChain::HDR_FORMAT,
+                1,
+                usage,
+                "[IBL] None",
+            );
+            return Self {
+                irradiance: placeholder,
+                prefiltered,
+                brdf_lut,
+                sampler,
+                environment: false,
+            };
+        };
+
+        // The environment and its mip levels, so that the samples of the convolutions
+        // can read an average of the texels around them instead of missing the small bright spots
+        let size = environment.size.width;
+        let levels = u32::BITS - size.leading_zeros();
+        let radiance = texture::Texture::create_cube(
+            device,
+            size,
+            PostChain::HDR_FORMAT,
+            levels,
+            usage,
+            "[IBL] Radiance",
+        );
+        let downsample = pipeline("fs_downsample");
+        faces.render(
+            &mut encoder,
+            &downsample,
+            &bind_group(&environment.view, 0.0),
+            &radiance.texture,
+            0,
+        );
+        for level in 1..levels {
+            let previous = radiance.texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("[IBL] Radiance level"),
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_mip_level: level - 1,
+                mip_level_count: NonZeroU32::new(1),
+                ..Default::default()
+            });
+            faces.render(
+                &mut encoder,
+                &downsample,
+                &bind_group(&previous, 0.0),
+                &radiance.texture,
+                level,
+            );
+        }
+
+        let irradiance = texture::Texture::create_cube(
+            device,
+            Ibl::IRRADIANCE_SIZE,
+            PostChain::HDR_FORMAT,
+            1,
+            usage,
+            "[IBL] Irradiance",
+        );
+        faces.render(
+            &mut encoder,
+            &pipeline("fs_irradiance"),
+            &bind_group(&radiance.view, 0.0),
+            &irradiance.texture,
+            0,
+        );
+
+        // Big enough for every level
+        let prefiltered_size =
+            size.clamp(1 << (Ibl::PREFILTERED_LEVELS - 1), Ibl::PREFILTERED_SIZE);
+        let prefiltered = texture::Texture::create_cube(
+            device,
+            prefiltered_size,
+            PostChain::HDR_FORMAT,
+            Ibl::PREFILTERED_LEVELS,
+            usage,
+            "[IBL] Prefiltered",
+        );
+        let prefilter = pipeline("fs_prefilter");
+        for level in 0..Ibl::PREFILTERED_LEVELS {
+            let roughness = level as f32 / (Ibl::PREFILTERED_LEVELS - 1) as f32;
+            faces.render(
+                &mut encoder,
+                &prefilter,
+                &bind_group(&radiance.view, roughness),
+                &prefiltered.texture,
+                level,
+            );
+        }
+        queue.submit(Some(encoder.finish()));
+
+        Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler,
+            environment: true,
+        }
+    }
+
+    /// Whether the maps come from an environment (else they are black)
+    pub fn has_environment(&self) -> bool {
+        self.environment
+    }
+
+    /// Irradiance and prefiltered cubemaps, the BRDF lookup table then their sampler
+    pub fn layout_entries(binding: u32) -> [wgpu::BindGroupLayoutEntry; 4] {
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        [
+            texture_entry(binding, wgpu::TextureViewDimension::Cube),
+            texture_entry(binding + 1, wgpu::TextureViewDimension::Cube),
+            texture_entry(binding + 2, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: binding + 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self, binding: u32) -> [wgpu::BindGroupEntry<'_>; 4] {
+        [
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding + 2,
+                resource: wgpu::BindingResource::TextureView(&self.brdf_lut.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding + 3,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+}
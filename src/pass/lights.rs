@@ -0,0 +1,242 @@
+use std::mem;
+
+use wgpu::{Device, Queue};
+
+use crate::model::{self, Vertex};
+
+// Uniform for light data, built from a `Light`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    pub(crate) position: [f32; 3],
+    // 0 = directional, 1 = point, 2 = spot
+    pub(crate) kind: u32,
+    pub(crate) color: [f32; 3],
+    pub(crate) intensity: f32,
+    // Where directional and spot lights shine to
+    pub(crate) direction: [f32; 3],
+    // Distance at which point and spot lights fade out completely
+    pub(crate) range: f32,
+    // Cosines of the spot inner and outer cone angles
+    pub(crate) cone: [f32; 2],
+    // Layer of the shadow map array, -1 when the light casts no shadow
+    pub(crate) shadow: i32,
+    pub(crate) shadow_bias: f32,
+    // World to shadow map clip space
+    pub(crate) view_proj: [[f32; 4]; 4],
+    pub(crate) normal_bias: f32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    pub(crate) _padding: [f32; 3],
+}
+
+impl LightUniform {
+    // The light buffer doubles as the instance buffer of the light models
+    const VERTEX_BUFFER_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: mem::size_of::<LightUniform>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 5,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                shader_location: 6,
+                format: wgpu::VertexFormat::Uint32,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                shader_location: 7,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+        ],
+    };
+}
+
+// Start of the light buffer, followed by `max_lights` lights
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsHeader {
+    count: u32,
+    // Lights are 16 byte aligned
+    _padding: [u32; 3],
+}
+
+/// The lights of the scene as the shaders see them (`shaders/lighting.wgsl`)
+///
+/// Lights go in a storage buffer when possible,
+/// otherwise (WebGL) in a uniform array that has to fit the uniform size limit
+pub struct LightBuffer {
+    // A header (light count) followed by `max_lights` lights
+    pub buffer: wgpu::Buffer,
+    use_storage: bool,
+    max_lights: usize,
+    count: u32,
+}
+
+impl LightBuffer {
+    const LIGHT_SIZE: wgpu::BufferAddress = mem::size_of::<LightUniform>() as wgpu::BufferAddress;
+    const HEADER_SIZE: wgpu::BufferAddress = mem::size_of::<LightsHeader>() as wgpu::BufferAddress;
+
+    pub fn new(device: &Device, max_lights: usize, label: &str) -> Self {
+        let limits = device.limits();
+        let use_storage = limits.max_storage_buffers_per_shader_stage > 0;
+        let max_binding_size = if use_storage {
+            limits.max_storage_buffer_binding_size
+        } else {
+            limits.max_uniform_buffer_binding_size
+        } as wgpu::BufferAddress;
+        let supported_lights =
+            ((max_binding_size - LightBuffer::HEADER_SIZE) / LightBuffer::LIGHT_SIZE) as usize;
+        let clamped = max_lights.clamp(1, supported_lights);
+        if clamped != max_lights {
+            log::warn!(
+                "{} lights requested, using {} (supported by this device)",
+                max_lights,
+                clamped
+            );
+        }
+
+        // The lights themselves live in the scene and are uploaded every frame
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: LightBuffer::HEADER_SIZE
+                + clamped as wgpu::BufferAddress * LightBuffer::LIGHT_SIZE,
+            usage: if use_storage {
+                wgpu::BufferUsages::STORAGE
+            } else {
+                wgpu::BufferUsages::UNIFORM
+            } | wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            buffer,
+            use_storage,
+            max_lights: clamped,
+            count: 0,
+        }
+    }
+
+    /// Number of lights the buffer holds at most
+    /// (what was asked for, within what the device supports)
+    pub fn max_lights(&self) -> usize {
+        self.max_lights
+    }
+
+    /// Number of lights uploaded by the last `write`
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn layout_entry(&self, binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: if self.use_storage {
+                    wgpu::BufferBindingType::Storage { read_only: true }
+                } else {
+                    wgpu::BufferBindingType::Uniform
+                },
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(self.buffer.size()),
+            },
+            count: None,
+        }
+    }
+
+    /// Prepends the lighting functions to a shader
+    /// The lights are declared as a storage buffer, they become
+    /// a uniform array of `max_lights` when storage buffers aren't supported
+    pub fn shader(&self, source: &str) -> String {
+        let source = format!("{}\n{}", include_str!("../shaders/lighting.wgsl"), source);
+        if self.use_storage {
+            return source;
+        }
+        source
+            .replace("var<storage, read> lights", "var<uniform> lights")
+            .replace(
+                "items: array<Light>,",
+                &format!("items: array<Light, {}>,", self.max_lights),
+            )
+    }
+
+    /// Uploads the lights, the ones past `max_lights` are ignored
+    pub fn write(&mut self, lights: &[LightUniform], queue: &Queue) {
+        let lights = &lights[..lights.len().min(self.max_lights)];
+        self.count = lights.len() as u32;
+
+        let header = LightsHeader {
+            count: self.count,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[header]));
+        if !lights.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                LightBuffer::HEADER_SIZE,
+                bytemuck::cast_slice(lights),
+            );
+        }
+    }
+
+    /// The lights as per instance vertex data, to draw one light model per light
+    pub fn instances(&self) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(LightBuffer::HEADER_SIZE..)
+    }
+}
+
+/// Pipeline drawing a model at the position of each light (`shaders/light.wgsl`)
+/// Only the globals (camera) are bound, no node is needed to draw the lights
+pub fn light_model_pipeline(
+    device: &Device,
+    label: &str,
+    global_bind_group_layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+    primitive: wgpu::PrimitiveState,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    multisample: wgpu::MultisampleState,
+) -> wgpu::RenderPipeline {
+    let light_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Light Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/light.wgsl").into()),
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[global_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &light_shader,
+            entry_point: "vs_main",
+            buffers: &[
+                model::ModelVertex::desc(),
+                LightUniform::VERTEX_BUFFER_LAYOUT,
+            ],
+        },
+        primitive,
+        depth_stencil,
+        multisample,
+        fragment: Some(wgpu::FragmentState {
+            module: &light_shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    alpha: wgpu::BlendComponent::REPLACE,
+                    color: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
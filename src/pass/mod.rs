@@ -1,18 +1,289 @@
-use wgpu::{Device, Queue, Surface};
+use std::collections::HashMap;
 
-use crate::{node::Node, particle::ParticleSystem};
+use wgpu::{Device, Queue};
 
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Camera,
+    graph::{self, AttachmentDesc, RenderContext, RenderGraph},
+    instance::InstanceBuffer,
+    model::{AlphaMode, Material},
+    node::{Node, NodeId, Nodes},
+    particle::ParticleSystem,
+    post::PostChain,
+    texture,
+};
+
+use self::{culling::CullingStats, lights::LightUniform};
+
+pub mod batch;
+pub mod culling;
+pub mod ibl;
+pub mod lights;
+pub mod particles;
+pub mod pbr;
 pub mod phong;
+pub mod scene;
+pub mod shadow;
+pub mod skybox;
+pub mod transparent;
 
 pub trait Pass {
-    fn draw(
+    // Adds the nodes drawing the scene to the frame's graph,
+    // they write `graph::SCENE` (in HDR, see `PostChain`) and `graph::DEPTH`
+    fn add_to_graph<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        nodes: &'a Nodes,
+        particle_systems: &'a [ParticleSystem],
+    );
+
+    // Uploads the view and projection of the camera
+    fn update_camera(&mut self, camera: &Camera, queue: &Queue);
+
+    // Allocates the resources of new nodes (and frees the ones of removed nodes),
+    // then uploads the dirty instances and the locals of the `changed` nodes
+    fn update_nodes(
         &mut self,
-        surface: &Surface,
+        nodes: &mut Nodes,
+        changed: &[NodeId],
         device: &Device,
         queue: &Queue,
-        nodes: &[Node],
-        particle_system: &[ParticleSystem],
-    ) -> Result<(), wgpu::SurfaceError>;
+    );
+
+    // Uploads the lights (in world space), the ones past `max_lights` are ignored
+    fn set_lights(&mut self, lights: &[LightUniform], queue: &Queue);
+
+    // Number of lights the pass can render at once
+    fn max_lights(&self) -> usize;
+
+    // Follows the new size of the frame (the graph takes care of the textures)
+    fn resize(&mut self, config: &wgpu::SurfaceConfiguration);
+
+    // Samples per pixel of the color and depth targets (1 without MSAA)
+    fn sample_count(&self) -> u32;
+
+    // What the last `update_nodes` culled with the last camera, drawn by the next frame
+    // (see `Culling`)
+    fn culling_stats(&self) -> CullingStats;
+}
+
+/// Factors of a material as the shaders see them (`Material` in `shaders/model.wgsl`
+/// and `shaders/pbr.wgsl`), each shading reads the ones it needs
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    base_color: [f32; 4],
+    specular: [f32; 3],
+    shininess: f32,
+    emissive: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    // Fragments with less alpha are discarded (0 unless the material is masked)
+    alpha_cutoff: f32,
+}
+
+impl MaterialUniform {
+    pub const SIZE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
+
+    /// A uniform buffer holding the factors of `material`
+    /// (materials don't change, so it is never updated)
+    pub fn buffer(device: &Device, material: &Material, label: &str) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(material)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        })
+    }
+}
+
+impl From<&Material> for MaterialUniform {
+    fn from(material: &Material) -> Self {
+        Self {
+            base_color: material.base_color_factor,
+            specular: material.specular_color,
+            shininess: material.shininess,
+            emissive: material.emissive_factor,
+            metallic: material.metallic_factor,
+            roughness: material.roughness_factor,
+            occlusion_strength: material.occlusion_strength,
+            normal_scale: material.normal_scale,
+            alpha_cutoff: match material.alpha_mode {
+                AlphaMode::Mask => material.alpha_cutoff,
+                AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+            },
+        }
+    }
+}
+
+/// The variants of a pass pipeline, `DrawModel` switches to the one fitting each material
+pub struct MaterialPipelines {
+    // Back faces culled
+    single_sided: wgpu::RenderPipeline,
+    double_sided: wgpu::RenderPipeline,
+    // Alpha blended, without depth writes (`AlphaMode::Blend`)
+    transparent_single_sided: wgpu::RenderPipeline,
+    transparent_double_sided: wgpu::RenderPipeline,
+}
+
+impl MaterialPipelines {
+    /// `create` builds the pipeline for a cull mode, transparent or not
+    /// (see `MaterialPipelines::color_target` and `MaterialPipelines::depth_write`)
+    pub fn new(mut create: impl FnMut(Option<wgpu::Face>, bool) -> wgpu::RenderPipeline) -> Self {
+        Self {
+            single_sided: create(Some(wgpu::Face::Back), false),
+            double_sided: create(None, false),
+            transparent_single_sided: create(Some(wgpu::Face::Back), true),
+            transparent_double_sided: create(None, true),
+        }
+    }
+
+    /// Blending of the color target
+    /// The alpha of opaque surfaces means nothing, so it isn't written (the target stays opaque)
+    pub fn color_target(format: wgpu::TextureFormat, transparent: bool) -> wgpu::ColorTargetState {
+        if transparent {
+            wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }
+        } else {
+            wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::COLOR,
+            }
+        }
+    }
+
+    /// Transparent surfaces are tested against the depth buffer but don't write to it,
+    /// or they would hide what is behind them when drawn first
+    pub fn depth_write(transparent: bool) -> bool {
+        !transparent
+    }
+
+    pub fn get(&self, material: &Material) -> &wgpu::RenderPipeline {
+        match (
+            material.alpha_mode == AlphaMode::Blend,
+            material.double_sided,
+        ) {
+            (false, false) => &self.single_sided,
+            (false, true) => &self.double_sided,
+            (true, false) => &self.transparent_single_sided,
+            (true, true) => &self.transparent_double_sided,
+        }
+    }
+}
+
+/// The attachments a pass draws the scene into
+/// With MSAA, the color goes to a multisampled attachment resolved into `graph::SCENE`
+pub struct SceneTargets {
+    sample_count: u32,
+}
+
+impl SceneTargets {
+    const MSAA: &'static str = "scene.msaa";
+    // wgpu can't tell which counts an adapter supports,
+    // but every adapter able to multisample supports 4 samples (and only 4 for sure)
+    pub const MSAA_SAMPLES: u32 = 4;
+
+    pub fn new(msaa: bool) -> Self {
+        Self {
+            sample_count: if msaa { Self::MSAA_SAMPLES } else { 1 },
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Multisample state of the pipelines drawing into the targets
+    pub fn multisample(&self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.sample_count,
+            ..Default::default()
+        }
+    }
+
+    /// Declares the attachments, returns the ones the node drawing the scene writes
+    pub fn add_attachments(&self, graph: &mut RenderGraph) -> Vec<&'static str> {
+        graph.add_attachment(graph::SCENE, AttachmentDesc::new(PostChain::HDR_FORMAT));
+        // Multisampled depth can only be rendered to (GL can't even create it otherwise)
+        let depth_usage = if self.sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+        };
+        graph.add_attachment(
+            graph::DEPTH,
+            AttachmentDesc::new(texture::Texture::DEPTH_FORMAT)
+                .with_sample_count(self.sample_count)
+                .with_usage(depth_usage),
+        );
+        if self.sample_count == 1 {
+            return vec![graph::SCENE, graph::DEPTH];
+        }
+
+        graph.add_attachment(
+            Self::MSAA,
+            AttachmentDesc::new(PostChain::HDR_FORMAT)
+                .with_sample_count(self.sample_count)
+                .with_usage(wgpu::TextureUsages::RENDER_ATTACHMENT),
+        );
+        vec![graph::SCENE, graph::DEPTH, Self::MSAA]
+    }
+
+    /// Color attachment of the scene, through the multisampled one if any
+    pub fn color_attachment<'r>(
+        &self,
+        ctx: &RenderContext<'r>,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> wgpu::RenderPassColorAttachment<'r> {
+        let (view, resolve_target) = match self.sample_count {
+            1 => (ctx.view(graph::SCENE), None),
+            _ => (ctx.view(Self::MSAA), Some(ctx.view(graph::SCENE))),
+        };
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target,
+            ops: wgpu::Operations { load, store: true },
+        }
+    }
+
+    /// Depth attachment of the scene, cleared to the far plane
+    pub fn depth_attachment<'r>(
+        &self,
+        ctx: &RenderContext<'r>,
+    ) -> wgpu::RenderPassDepthStencilAttachment<'r> {
+        wgpu::RenderPassDepthStencilAttachment {
+            view: ctx.view(graph::DEPTH),
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: true,
+            }),
+            stencil_ops: None,
+        }
+    }
+}
+
+/// The view of an optional material map, or of the texture standing in for it
+pub fn view_or<'a>(
+    texture: &'a Option<texture::Texture>,
+    fallback: &'a texture::Texture,
+) -> &'a wgpu::TextureView {
+    &texture.as_ref().unwrap_or(fallback).view
 }
 
 /// Uniform buffer pool
@@ -20,7 +291,9 @@ pub trait Pass {
 /// and provides a way to update uniforms to render pipeline
 pub struct UniformPool {
     label: &'static str,
-    pub buffers: Vec<wgpu::Buffer>,
+    buffers: HashMap<NodeId, wgpu::Buffer>,
+    // Buffers of removed nodes, reused by the next allocations
+    spare: Vec<wgpu::Buffer>,
     size: u64,
 }
 
@@ -28,31 +301,131 @@ impl UniformPool {
     pub fn new(label: &'static str, size: u64) -> Self {
         Self {
             label,
-            buffers: Vec::new(),
+            buffers: HashMap::new(),
+            spare: Vec::new(),
             size,
         }
     }
 
-    pub fn alloc_buffers(&mut self, count: usize, device: &Device) {
-        // We reset the buffers each time we allocate
-        // TODO: Ideally we should keep track of the object it belongs to,
-        // so we can add/remove objects (and their uniform buffers) dynamically
-        self.buffers = Vec::new();
+    /// Gives `node` a uniform buffer (its content is undefined until updated)
+    pub fn alloc(&mut self, node: NodeId, device: &Device) -> &wgpu::Buffer {
+        let spare = &mut self.spare;
+        let (label, size) = (self.label, self.size);
+        self.buffers.entry(node).or_insert_with(|| {
+            spare.pop().unwrap_or_else(|| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(label),
+                    size,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+        })
+    }
+
+    /// Releases the buffer of `node`, it will be reused by another node
+    pub fn free(&mut self, node: NodeId) {
+        if let Some(buffer) = self.buffers.remove(&node) {
+            self.spare.push(buffer);
+        }
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&wgpu::Buffer> {
+        self.buffers.get(&node)
+    }
 
-        for _ in 0..count {
-            let local_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some(self.label),
-                size: self.size,
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                mapped_at_creation: false,
-            });
-            self.buffers.push(local_uniform_buffer);
+    pub fn update_uniform<T: bytemuck::Pod>(&self, node: NodeId, data: T, queue: &Queue) {
+        if let Some(buffer) = self.buffers.get(&node) {
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[data]));
         }
     }
+}
+
+/// GPU resources of every node: local uniforms, per material bind groups and instances
+/// The bind groups are up to each pass, the rest is the same for all of them
+pub struct NodeResources {
+    pub uniform_pool: UniformPool,
+    bind_groups: HashMap<NodeId, Vec<wgpu::BindGroup>>,
+    instance_buffers: HashMap<NodeId, InstanceBuffer>,
+}
+
+impl NodeResources {
+    pub fn new(label: &'static str, locals_size: u64) -> Self {
+        Self {
+            uniform_pool: UniformPool::new(label, locals_size),
+            bind_groups: HashMap::new(),
+            instance_buffers: HashMap::new(),
+        }
+    }
+
+    /// Brings the resources in sync with `nodes`, `bind_groups` creates the ones of new nodes
+    /// (one per material, from the node and its local uniform buffer)
+    pub fn update(
+        &mut self,
+        nodes: &mut Nodes,
+        changed: &[NodeId],
+        device: &Device,
+        queue: &Queue,
+        mut bind_groups: impl FnMut(&Node, &wgpu::Buffer) -> Vec<wgpu::BindGroup>,
+    ) {
+        for (id, node) in nodes.iter() {
+            if self.instance_buffers.contains_key(&id) {
+                continue;
+            }
+
+            #[cfg(debug_assertions)]
+            log::debug!("Allocating resources for {:?}", id);
+
+            let local_buffer = self.uniform_pool.alloc(id, device);
+            self.bind_groups.insert(id, bind_groups(node, local_buffer));
+            // Filled below, new nodes have all their instances dirty
+            let instance_buffer = InstanceBuffer::new(device, node.instances().len());
+            self.instance_buffers.insert(id, instance_buffer);
+        }
+
+        // Every node has its resources now, anything left over belongs to removed nodes
+        if self.instance_buffers.len() > nodes.len() {
+            let removed = self
+                .instance_buffers
+                .keys()
+                .filter(|id| !nodes.contains(**id))
+                .copied()
+                .collect::<Vec<_>>();
+            for id in removed {
+                #[cfg(debug_assertions)]
+                log::debug!("Freeing resources of {:?}", id);
 
-    pub fn update_uniform<T: bytemuck::Pod>(&self, index: usize, data: T, queue: &Queue) {
-        if !self.buffers.is_empty() {
-            queue.write_buffer(&self.buffers[index], 0, bytemuck::cast_slice(&[data]));
+                self.uniform_pool.free(id);
+                self.bind_groups.remove(&id);
+                self.instance_buffers.remove(&id);
+            }
         }
+
+        // Only upload the instances that changed since the last update
+        for (id, node) in nodes.iter_mut() {
+            let dirty = node.take_dirty_instances();
+            if dirty.is_empty() {
+                continue;
+            }
+            if let Some(instance_buffer) = self.instance_buffers.get_mut(&id) {
+                instance_buffer.update(device, queue, node.instances(), &dirty);
+            }
+        }
+
+        // Only upload the nodes that actually moved (new nodes always count as moved)
+        for id in changed {
+            if let Some(node) = nodes.get(*id) {
+                self.uniform_pool.update_uniform(*id, node.locals(), queue);
+            }
+        }
+    }
+
+    /// The instance buffer and bind groups of a node,
+    /// None for nodes added since the last update
+    pub fn get(&self, id: NodeId) -> Option<(&InstanceBuffer, &[wgpu::BindGroup])> {
+        Some((
+            self.instance_buffers.get(&id)?,
+            self.bind_groups.get(&id)?.as_slice(),
+        ))
     }
 }
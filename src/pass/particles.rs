@@ -0,0 +1,157 @@
+use cgmath::{Matrix, SquareMatrix};
+use wgpu::{util::DeviceExt, Device, Queue};
+
+use crate::{
+    camera::{Camera, Projection},
+    particle::{Particle, ParticleBlend, ParticleSystem},
+    post::PostChain,
+    texture,
+};
+
+use super::MaterialPipelines;
+
+// `Camera` in `shaders/particle.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleCamera {
+    view_proj: [[f32; 4]; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+}
+
+/// Draws the particle systems as quads facing the camera, after everything else
+/// Particles are tested against the depth buffer but don't write to it
+pub struct ParticleRenderer {
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    additive: wgpu::RenderPipeline,
+    alpha: wgpu::RenderPipeline,
+}
+
+impl ParticleRenderer {
+    pub fn new(device: &Device, multisample: wgpu::MultisampleState) -> Self {
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("[Particles] Camera layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("[Particles] Camera"),
+            contents: bytemuck::cast_slice(&[ParticleCamera {
+                view_proj: cgmath::Matrix4::identity().into(),
+                right: [1.0, 0.0, 0.0, 0.0],
+                up: [0.0, 1.0, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("[Particles] Camera bind group"),
+            layout: &camera_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("[Particles] Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/particle.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("[Particles] Pipeline"),
+            bind_group_layouts: &[&camera_layout, &ParticleSystem::bind_group_layout(device)],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, target| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Particle::desc()],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: Default::default(),
+                    bias: Default::default(),
+                }),
+                multisample,
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(target)],
+                }),
+                multiview: None,
+            })
+        };
+
+        Self {
+            additive: pipeline(
+                "[Particles] Additive pipeline",
+                wgpu::ColorTargetState {
+                    format: PostChain::HDR_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                },
+            ),
+            alpha: pipeline(
+                "[Particles] Alpha pipeline",
+                MaterialPipelines::color_target(PostChain::HDR_FORMAT, true),
+            ),
+            camera_buffer,
+            camera_bind_group,
+        }
+    }
+
+    /// The quads face the screen, their axes come from the view
+    pub fn update_camera(&self, camera: &Camera, projection: &Projection, queue: &Queue) {
+        let view = camera.calc_matrix();
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[ParticleCamera {
+                view_proj: (projection.calc_matrix() * view).into(),
+                right: view.row(0).truncate().extend(0.0).into(),
+                up: view.row(1).truncate().extend(0.0).into(),
+            }]),
+        );
+    }
+
+    /// Changes the pipeline and bind group 0
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        particle_systems: &'a [ParticleSystem],
+    ) {
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        for particle_system in particle_systems {
+            render_pass.set_pipeline(match particle_system.appearance.blend {
+                ParticleBlend::Additive => &self.additive,
+                ParticleBlend::Alpha => &self.alpha,
+            });
+            particle_system.render(render_pass);
+        }
+    }
+}
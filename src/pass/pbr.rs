@@ -0,0 +1,90 @@
+use wgpu::{BindGroupLayout, Device};
+
+use crate::node::Node;
+
+use super::{
+    scene::{MaterialFallbacks, SceneShader},
+    view_or, MaterialUniform,
+};
+
+/// Physically based shading, for the metallic-roughness materials of glTF (`shaders/pbr.wgsl`)
+pub fn shader() -> SceneShader {
+    SceneShader {
+        label: "[PBR]",
+        source: include_str!("../shaders/pbr.wgsl"),
+        // Metallic-roughness, occlusion and emissive maps
+        material_entries: vec![
+            SceneShader::texture_entry(4),
+            SceneShader::texture_entry(5),
+            SceneShader::texture_entry(6),
+        ],
+        local_bind_groups,
+    }
+}
+
+// One bind group per material, with the node's locals
+fn local_bind_groups(
+    device: &Device,
+    layout: &BindGroupLayout,
+    fallbacks: &MaterialFallbacks,
+    node: &Node,
+    local_buffer: &wgpu::Buffer,
+) -> Vec<wgpu::BindGroup> {
+    node.model
+        .materials
+        .iter()
+        .map(|material| {
+            let material_buffer = MaterialUniform::buffer(device, material, "[PBR] Material");
+
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("[PBR] Locals"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: local_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(
+                            &material.diffuse_texture.view,
+                        ),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(view_or(
+                            &material.normal_texture,
+                            &fallbacks.flat_normal,
+                        )),
+                    },
+                    // The bind group keeps the buffer alive
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: material_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(view_or(
+                            &material.metallic_roughness_texture,
+                            &fallbacks.white,
+                        )),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: wgpu::BindingResource::TextureView(view_or(
+                            &material.occlusion_texture,
+                            &fallbacks.white,
+                        )),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: wgpu::BindingResource::TextureView(view_or(
+                            &material.emissive_texture,
+                            &fallbacks.white,
+                        )),
+                    },
+                ],
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,543 @@
+use std::mem;
+
+use wgpu::{BindGroupLayout, Device, Queue};
+
+use crate::{
+    camera::{Camera, CameraUniform, Projection},
+    graph::{RenderContext, RenderGraph},
+    instance::InstanceRaw,
+    model::{self, DrawLight, Model, Vertex},
+    node::{Node, NodeId, Nodes},
+    particle::ParticleSystem,
+    post::PostChain,
+    texture,
+};
+
+use super::{
+    batch::{DrawBatches, DrawMode},
+    culling::{Culling, CullingStats},
+    ibl::Ibl,
+    lights::{self, LightBuffer, LightUniform},
+    particles::ParticleRenderer,
+    phong::{Locals, PhongConfig},
+    shadow::{ShadowMaps, SHADOW_MAPS},
+    skybox::Skybox,
+    transparent::TransparentPhase,
+    MaterialPipelines, MaterialUniform, NodeResources, Pass, SceneTargets,
+};
+
+// Global uniform data
+// aka camera position and ambient light color
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    camera: CameraUniform,
+    // Flat ambient color, w is 1 when the environment lights the scene instead (see `Ibl`)
+    ambient: [f32; 4],
+}
+
+/// Stand in for the maps a material doesn't have
+pub struct MaterialFallbacks {
+    pub white: texture::Texture,
+    pub flat_normal: texture::Texture,
+}
+
+/// Creates the local bind groups of a node, one per material, with its local uniform buffer
+pub type LocalBindGroups =
+    fn(&Device, &BindGroupLayout, &MaterialFallbacks, &Node, &wgpu::Buffer) -> Vec<wgpu::BindGroup>;
+
+/// What sets a shading apart: the shader of its pipelines and how it binds the materials
+/// (see `phong::shader` and `pbr::shader`)
+pub struct SceneShader {
+    // Prefixes the labels of the shader, pipelines and material bind groups
+    pub label: &'static str,
+    // Lights and shadows come from lighting.wgsl, prepended to it
+    pub source: &'static str,
+    // Every local bind group starts with the locals, base color map, normal map and
+    // material factors (bindings 0 to 3, the shadow maps read them too), the rest is up to the shader
+    pub material_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    pub local_bind_groups: LocalBindGroups,
+}
+
+impl SceneShader {
+    /// Layout entry of a 2D material map
+    pub fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    /// Layout entry of the material factors (see `MaterialUniform`)
+    pub fn material_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(MaterialUniform::SIZE),
+            },
+            count: None,
+        }
+    }
+}
+
+/// Draws the nodes, lights, skybox and particles of the scene with a `SceneShader`
+/// Everything but the materials is shared by the shadings: lights, shadows, culling,
+/// batching and the transparent phase
+pub struct ScenePass {
+    // Uniforms
+    global_uniform_buffer: wgpu::Buffer,
+    global_bind_group: wgpu::BindGroup,
+    local_bind_group_layout: BindGroupLayout,
+    local_bind_groups: LocalBindGroups,
+    // Local uniforms, bind groups and instances of each node
+    nodes: NodeResources,
+    // Textures
+    targets: SceneTargets,
+    fallbacks: MaterialFallbacks,
+    // Render pipeline
+    // One per cull mode, materials pick theirs
+    render_pipelines: MaterialPipelines,
+    // Lighting
+    lights: LightBuffer,
+    light_render_pipeline: wgpu::RenderPipeline,
+    shadow_maps: ShadowMaps,
+    // Flat ambient color, or the skybox lighting the scene (uploaded with the camera)
+    ambient: [f32; 4],
+    // Drawn behind the scene instead of the clear color
+    skybox: Option<Skybox>,
+    // Mesh instances outside the camera frustum aren't drawn
+    culling: Culling,
+    // Visible opaque meshes, grouped to be drawn with few state changes
+    batches: DrawBatches,
+    // Blended meshes, drawn last
+    transparent: TransparentPhase,
+    // Drawn over everything
+    particles: ParticleRenderer,
+    // Camera
+    camera_uniform: CameraUniform,
+    projection: Projection,
+    light_model: Option<Model>,
+}
+
+impl ScenePass {
+    const GLOBAL_SIZE: wgpu::BufferAddress = mem::size_of::<Globals>() as wgpu::BufferAddress;
+    const LOCAL_SIZE: wgpu::BufferAddress = mem::size_of::<Locals>() as wgpu::BufferAddress;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        phong_config: &PhongConfig,
+        shader: SceneShader,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        camera: &Camera,
+        light_model: Option<Model>,
+        skybox: Option<texture::Texture>,
+    ) -> ScenePass {
+        let lights = LightBuffer::new(device, phong_config.max_lights, "[Scene] Lights");
+
+        // Setup global uniforms
+        // Global bind group layout
+        let global_bind_group_layout = {
+            let [shadow_maps, shadow_sampler] = ShadowMaps::layout_entries(3);
+            let [irradiance, prefiltered, brdf_lut, ibl_sampler] = Ibl::layout_entries(5);
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("[Scene] Globals"),
+                entries: &[
+                    // Global uniforms
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(ScenePass::GLOBAL_SIZE),
+                        },
+                        count: None,
+                    },
+                    // Lights
+                    lights.layout_entry(1),
+                    // Sampler for textures
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Shadow maps
+                    shadow_maps,
+                    shadow_sampler,
+                    irradiance,
+                    prefiltered,
+                    brdf_lut,
+                    ibl_sampler,
+                ],
+            })
+        };
+
+        // Setup local uniforms
+        // Local bind group layout, the shared bindings then the material maps of the shader
+        let local_bind_group_layout = {
+            let mut entries = vec![
+                // Local uniforms
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(ScenePass::LOCAL_SIZE),
+                    },
+                    count: None,
+                },
+                // Base color map
+                SceneShader::texture_entry(1),
+                // Normal map
+                SceneShader::texture_entry(2),
+                // Material factors
+                SceneShader::material_entry(3),
+            ];
+            entries.extend_from_slice(&shader.material_entries);
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(&format!("{} Locals", shader.label)),
+                entries: &entries,
+            })
+        };
+
+        let shadow_maps = ShadowMaps::new(
+            device,
+            phong_config.shadow_maps(),
+            phong_config.shadow_map_size,
+            &local_bind_group_layout,
+        );
+        // The skybox also lights the scene
+        let ibl = Ibl::new(device, queue, skybox.as_ref());
+        let ambient = phong_config.ambient;
+        let ambient = [
+            ambient[0],
+            ambient[1],
+            ambient[2],
+            if ibl.has_environment() { 1.0 } else { 0.0 },
+        ];
+
+        // Combine the global uniform, the lights, the texture sampler and the shadow maps into one bind group
+        let (global_uniform_buffer, global_bind_group) = {
+            // Global uniform buffer
+            let global_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("[Scene] Globals"),
+                size: ScenePass::GLOBAL_SIZE,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            // We also need a sampler for our textures
+            // Repeating them is the default wrap mode of glTF
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("[Scene] sampler"),
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                min_filter: wgpu::FilterMode::Linear,
+                mag_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            });
+
+            let [shadow_maps, shadow_sampler] = shadow_maps.bind_group_entries(3);
+            let [irradiance, prefiltered, brdf_lut, ibl_sampler] = ibl.bind_group_entries(5);
+            let global_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("[Scene] Globals"),
+                layout: &global_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: global_uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: lights.buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    shadow_maps,
+                    shadow_sampler,
+                    irradiance,
+                    prefiltered,
+                    brdf_lut,
+                    ibl_sampler,
+                ],
+            });
+
+            (global_uniform_buffer, global_bind_group)
+        };
+
+        // Setup the render pipeline
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline", shader.label)),
+            bind_group_layouts: &[&global_bind_group_layout, &local_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let targets = SceneTargets::new(phong_config.msaa);
+        let depth_stencil = Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: Default::default(),
+            bias: Default::default(),
+        });
+        let primitive = wgpu::PrimitiveState {
+            cull_mode: Some(wgpu::Face::Back),
+            // Enable/disable wireframe mode
+            topology: if phong_config.wireframe {
+                wgpu::PrimitiveTopology::LineList
+            } else {
+                wgpu::PrimitiveTopology::TriangleList
+            },
+            ..Default::default()
+        };
+
+        let render_pipelines = {
+            let vertex_buffers = [model::ModelVertex::desc(), InstanceRaw::desc()];
+
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&format!("{} Shader", shader.label)),
+                source: wgpu::ShaderSource::Wgsl(lights.shader(shader.source).into()),
+            });
+            let label = format!("{} Pipeline", shader.label);
+            MaterialPipelines::new(|cull_mode, transparent| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(&label),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader_module,
+                        entry_point: "vs_main",
+                        buffers: &vertex_buffers,
+                    },
+                    primitive: wgpu::PrimitiveState {
+                        cull_mode,
+                        ..primitive
+                    },
+                    depth_stencil: depth_stencil.clone().map(|depth_stencil| {
+                        wgpu::DepthStencilState {
+                            depth_write_enabled: MaterialPipelines::depth_write(transparent),
+                            ..depth_stencil
+                        }
+                    }),
+                    multisample: targets.multisample(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader_module,
+                        entry_point: "fs_main",
+                        targets: &[Some(MaterialPipelines::color_target(
+                            PostChain::HDR_FORMAT,
+                            transparent,
+                        ))],
+                    }),
+                    multiview: None,
+                })
+            })
+        };
+
+        let light_render_pipeline = lights::light_model_pipeline(
+            device,
+            "[Scene] Light Pipeline",
+            &global_bind_group_layout,
+            PostChain::HDR_FORMAT,
+            primitive,
+            depth_stencil,
+            targets.multisample(),
+        );
+
+        // Setup camera uniform
+        let projection =
+            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(camera, &projection);
+
+        let skybox = skybox.map(|cubemap| Skybox::new(device, cubemap, targets.multisample()));
+        let particles = ParticleRenderer::new(device, targets.multisample());
+
+        ScenePass {
+            global_uniform_buffer,
+            global_bind_group,
+            local_bind_group_layout,
+            local_bind_groups: shader.local_bind_groups,
+            nodes: NodeResources::new("[Scene] Locals", ScenePass::LOCAL_SIZE),
+            targets,
+            fallbacks: MaterialFallbacks {
+                white: texture::Texture::from_color(device, queue, [255; 4], "[Scene] White"),
+                flat_normal: texture::Texture::flat_normal(device, queue, "[Scene] Flat normal"),
+            },
+            render_pipelines,
+            lights,
+            light_render_pipeline,
+            shadow_maps,
+            ambient,
+            skybox,
+            culling: Culling::new(),
+            batches: DrawBatches::new(DrawMode::new(phong_config.indirect_draws, device), device),
+            transparent: TransparentPhase::new(),
+            particles,
+            camera_uniform,
+            projection,
+            light_model,
+        }
+    }
+}
+
+fn render_pass(
+    ctx: &mut RenderContext,
+    scene_pass: &ScenePass,
+    nodes: &Nodes,
+    particle_systems: &[ParticleSystem],
+) {
+    let color_attachment = scene_pass.targets.color_attachment(
+        ctx,
+        // Set the clear color during redraw
+        // This is basically a background color applied if an object isn't taking up space
+        wgpu::LoadOp::Clear(wgpu::Color {
+            r: 0.1,
+            g: 0.2,
+            b: 0.3,
+            a: 1.0,
+        }),
+    );
+    let depth_attachment = scene_pass.targets.depth_attachment(ctx);
+    let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(color_attachment)],
+        // Create a depth stencil buffer using the depth texture
+        depth_stencil_attachment: Some(depth_attachment),
+    });
+
+    // Local uniform buffers, bind groups and instance buffers are allocated
+    // by `Pass::update_nodes` before drawing, so every node has them by now
+
+    if let Some(light_model) = &scene_pass.light_model {
+        // Setup lighting pipeline
+        render_pass.set_pipeline(&scene_pass.light_render_pipeline);
+        // One light model per active light, placed by the light buffer
+        render_pass.set_vertex_buffer(1, scene_pass.lights.instances());
+        render_pass.draw_light_model_instanced(
+            light_model,
+            0..scene_pass.lights.count(),
+            &scene_pass.global_bind_group,
+        );
+    }
+
+    render_pass.set_bind_group(0, &scene_pass.global_bind_group, &[]);
+
+    // Render/draw the opaque mesh instances the camera sees
+    scene_pass.batches.render(
+        &mut render_pass,
+        nodes,
+        &scene_pass.nodes,
+        &scene_pass.render_pipelines,
+    );
+
+    // The sky fills what the opaque meshes left, the blended ones go over it
+    if let Some(skybox) = &scene_pass.skybox {
+        skybox.render(&mut render_pass);
+        render_pass.set_bind_group(0, &scene_pass.global_bind_group, &[]);
+    }
+
+    // Blended meshes go over everything opaque, back to front
+    scene_pass.transparent.render(
+        &mut render_pass,
+        nodes,
+        &scene_pass.nodes,
+        &scene_pass.render_pipelines,
+    );
+
+    scene_pass
+        .particles
+        .render(&mut render_pass, particle_systems);
+}
+
+impl Pass for ScenePass {
+    fn add_to_graph<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        nodes: &'a Nodes,
+        particle_systems: &'a [ParticleSystem],
+    ) {
+        let writes = self.targets.add_attachments(graph);
+        graph.add_node("[Scene] Shadows", &[], &[SHADOW_MAPS], move |ctx| {
+            self.shadow_maps.render(ctx.encoder, nodes, &self.nodes)
+        });
+        graph.add_node("[Scene] Scene", &[SHADOW_MAPS], &writes, move |ctx| {
+            render_pass(ctx, self, nodes, particle_systems)
+        });
+    }
+
+    fn update_camera(&mut self, camera: &Camera, queue: &Queue) {
+        self.transparent.set_camera(camera);
+        self.camera_uniform
+            .update_view_proj(camera, &self.projection);
+        self.culling.set_camera(&self.camera_uniform);
+        if let Some(skybox) = &self.skybox {
+            skybox.update_camera(camera, &self.projection, queue);
+        }
+        self.particles
+            .update_camera(camera, &self.projection, queue);
+        queue.write_buffer(
+            &self.global_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[Globals {
+                camera: self.camera_uniform,
+                ambient: self.ambient,
+            }]),
+        );
+    }
+
+    fn update_nodes(
+        &mut self,
+        nodes: &mut Nodes,
+        changed: &[NodeId],
+        device: &Device,
+        queue: &Queue,
+    ) {
+        let (layout, fallbacks, local_bind_groups) = (
+            &self.local_bind_group_layout,
+            &self.fallbacks,
+            self.local_bind_groups,
+        );
+        self.nodes
+            .update(nodes, changed, device, queue, |node, local_buffer| {
+                local_bind_groups(device, layout, fallbacks, node, local_buffer)
+            });
+        self.culling.cull(nodes);
+        self.batches.update(nodes, &self.culling, device, queue);
+        self.transparent.sort(nodes, &self.culling);
+    }
+
+    fn set_lights(&mut self, lights: &[LightUniform], queue: &Queue) {
+        let mut lights = lights[..lights.len().min(self.lights.max_lights())].to_vec();
+        self.shadow_maps.assign(&mut lights, queue);
+        self.lights.write(&lights, queue);
+    }
+
+    fn max_lights(&self) -> usize {
+        self.lights.max_lights()
+    }
+
+    fn resize(&mut self, config: &wgpu::SurfaceConfiguration) {
+        self.projection.resize(config.width, config.height);
+    }
+
+    fn sample_count(&self) -> u32 {
+        self.targets.sample_count()
+    }
+
+    fn culling_stats(&self) -> CullingStats {
+        self.culling.stats()
+    }
+}
@@ -0,0 +1,334 @@
+use std::{mem, num::NonZeroU32};
+
+use wgpu::{BindGroupLayout, Device, Queue};
+
+use crate::{
+    model::{self, AlphaMode, DrawModel, Vertex},
+    node::Nodes,
+    texture,
+};
+
+use super::{lights::LightUniform, NodeResources};
+
+/// Name of the shadow maps in the render graph, the node rendering them writes it
+pub const SHADOW_MAPS: &str = "shadow_maps";
+
+// One layer of the shadow map array and the light matrix it is rendered with
+struct ShadowMap {
+    view: wgpu::TextureView,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Depth of the scene from each shadow casting light, one layer of an array per light
+/// Sampled by `shadow_factor` in `shaders/lighting.wgsl`
+pub struct ShadowMaps {
+    pipeline: wgpu::RenderPipeline,
+    // Also alpha tests the fragments, for masked materials
+    mask_pipeline: wgpu::RenderPipeline,
+    maps: Vec<ShadowMap>,
+    // Layers rendered this frame
+    count: usize,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+}
+
+impl ShadowMaps {
+    const UNIFORM_SIZE: wgpu::BufferAddress =
+        mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+
+    /// `max_shadows` layers of `size` squared (0 layers disable shadows)
+    /// `locals_layout` is the one of the pass bind groups, it must start with the `Locals`,
+    /// the base color map and the material factors (see `SceneShader::material_entries`)
+    pub fn new(
+        device: &Device,
+        max_shadows: usize,
+        size: u32,
+        locals_layout: &BindGroupLayout,
+    ) -> Self {
+        let limits = device.limits();
+        let max_shadows = max_shadows.min(limits.max_texture_array_layers as usize);
+        // Without shadows, a single texel still has to be bound
+        let size = if max_shadows > 0 {
+            size.clamp(1, limits.max_texture_dimension_2d)
+        } else {
+            1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow maps"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: max_shadows.max(1) as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow maps"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // Compares the depth of the lit point with the shadow map (1 when lit, 0 in shadow)
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // Same as the scene sampler, for the base color of masked materials
+        // (kept alive by the bind groups)
+        let material_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow material sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(ShadowMaps::UNIFORM_SIZE),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline"),
+            bind_group_layouts: &[&bind_group_layout, locals_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/shadow.wgsl").into()),
+        });
+
+        // Depth only, from the point of view of each shadow casting light
+        // `fragment` alpha tests masked materials
+        let create_pipeline = |label, fragment| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[
+                        model::ModelVertex::desc(),
+                        crate::instance::InstanceRaw::desc(),
+                    ],
+                },
+                primitive: wgpu::PrimitiveState {
+                    // Planes and other open meshes should cast shadows from both sides
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: Default::default(),
+                    // Steep surfaces need more bias than the per-light one
+                    bias: wgpu::DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    },
+                }),
+                multisample: Default::default(),
+                fragment,
+                multiview: None,
+            })
+        };
+        let pipeline = create_pipeline("Shadow Pipeline", None);
+        let mask_pipeline = create_pipeline(
+            "Shadow Mask Pipeline",
+            Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_mask",
+                targets: &[],
+            }),
+        );
+
+        let maps = (0..max_shadows as u32)
+            .map(|layer| {
+                let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow map"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                    ..Default::default()
+                });
+                let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Shadow"),
+                    size: ShadowMaps::UNIFORM_SIZE,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Shadow"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&material_sampler),
+                        },
+                    ],
+                });
+
+                ShadowMap {
+                    view,
+                    uniform_buffer,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            mask_pipeline,
+            maps,
+            count: 0,
+            view,
+            sampler,
+        }
+    }
+
+    /// The shadow map array then its comparison sampler, from `binding`
+    pub fn layout_entries(binding: u32) -> [wgpu::BindGroupLayoutEntry; 2] {
+        [
+            wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: binding + 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+        ]
+    }
+
+    pub fn bind_group_entries(&self, binding: u32) -> [wgpu::BindGroupEntry<'_>; 2] {
+        [
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: binding + 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Gives a shadow map to the shadow casters while there are some left
+    /// (`Light::to_uniform` marks them with 0) and uploads their matrices
+    pub fn assign(&mut self, lights: &mut [LightUniform], queue: &Queue) {
+        self.count = 0;
+        for light in lights.iter_mut().filter(|light| light.shadow >= 0) {
+            let Some(shadow_map) = self.maps.get(self.count) else {
+                light.shadow = -1;
+                continue;
+            };
+            queue.write_buffer(
+                &shadow_map.uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[light.view_proj]),
+            );
+            light.shadow = self.count as i32;
+            self.count += 1;
+        }
+    }
+
+    /// Renders the depth of every node from each shadow casting light
+    /// Blended meshes don't cast shadows, masked ones only where they aren't cut out
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        nodes: &Nodes,
+        resources: &NodeResources,
+    ) {
+        for shadow_map in &self.maps[..self.count] {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &shadow_map.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            render_pass.set_bind_group(0, &shadow_map.bind_group, &[]);
+
+            for (id, node) in nodes.iter() {
+                let Some((instance_buffer, local_bind_groups)) = resources.get(id) else {
+                    continue;
+                };
+
+                render_pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+                for mesh in &node.model.meshes {
+                    let (Some(material), Some(bind_group)) = (
+                        node.model.materials.get(mesh.material),
+                        local_bind_groups.get(mesh.material),
+                    ) else {
+                        continue;
+                    };
+                    // Blended meshes let the light through
+                    let pipeline = match material.alpha_mode {
+                        AlphaMode::Opaque => &self.pipeline,
+                        AlphaMode::Mask => &self.mask_pipeline,
+                        AlphaMode::Blend => continue,
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        material,
+                        0..node.instances().len() as u32,
+                        bind_group,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+}
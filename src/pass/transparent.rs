@@ -0,0 +1,117 @@
+use cgmath::{ElementWise, EuclideanSpace, InnerSpace, Point3};
+
+use crate::{
+    camera::Camera,
+    model::{AlphaMode, DrawModel},
+    node::{NodeId, Nodes},
+};
+
+use super::{culling::Culling, MaterialPipelines, NodeResources};
+
+// One instance of a blended mesh, drawn on its own so it can be sorted
+struct TransparentDraw {
+    node: NodeId,
+    mesh: usize,
+    instance: u32,
+    // Squared, from the camera to the center of the mesh
+    distance: f32,
+}
+
+/// Blended meshes (`AlphaMode::Blend`) are skipped by `DrawModel::draw_model_instanced`
+/// and drawn here after every opaque mesh, from the farthest to the nearest
+pub struct TransparentPhase {
+    camera_position: Point3<f32>,
+    draws: Vec<TransparentDraw>,
+}
+
+impl TransparentPhase {
+    pub fn new() -> Self {
+        Self {
+            camera_position: Point3::origin(),
+            draws: Vec::new(),
+        }
+    }
+
+    pub fn set_camera(&mut self, camera: &Camera) {
+        self.camera_position = camera.position;
+    }
+
+    /// Collects the visible blended meshes of `nodes` and sorts them back to front,
+    /// called each frame (after `Culling::cull`) since both the camera and the nodes may have moved
+    pub fn sort(&mut self, nodes: &Nodes, culling: &Culling) {
+        self.draws.clear();
+        for (id, node) in nodes.iter() {
+            let Some(visible) = culling.visible(id) else {
+                continue;
+            };
+            let world_matrix = node.world_matrix();
+            for ((mesh_index, mesh), ranges) in node.model.meshes.iter().enumerate().zip(visible) {
+                let blended = node
+                    .model
+                    .materials
+                    .get(mesh.material)
+                    .is_some_and(|material| material.alpha_mode == AlphaMode::Blend);
+                if !blended {
+                    continue;
+                }
+
+                let center = mesh.bounds.aabb.center().to_vec();
+                for instance_index in ranges.iter().cloned().flatten() {
+                    let instance = &node.instances()[instance_index as usize];
+                    let local = instance.position
+                        + instance.rotation * instance.scale.mul_element_wise(center);
+                    let world = world_matrix * local.extend(1.0);
+                    let distance = (world.truncate() - self.camera_position.to_vec()).magnitude2();
+                    self.draws.push(TransparentDraw {
+                        node: id,
+                        mesh: mesh_index,
+                        instance: instance_index,
+                        distance,
+                    });
+                }
+            }
+        }
+
+        self.draws.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+    }
+
+    /// Draws the meshes sorted by the last `sort`,
+    /// the global bind group must already be set on `render_pass`
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        nodes: &'a Nodes,
+        resources: &'a NodeResources,
+        pipelines: &'a MaterialPipelines,
+    ) {
+        for draw in &self.draws {
+            let (Some(node), Some((instance_buffer, bind_groups))) =
+                (nodes.get(draw.node), resources.get(draw.node))
+            else {
+                continue;
+            };
+            let mesh = &node.model.meshes[draw.mesh];
+            let (Some(material), Some(bind_group)) = (
+                node.model.materials.get(mesh.material),
+                bind_groups.get(mesh.material),
+            ) else {
+                continue;
+            };
+
+            render_pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+            render_pass.draw_mesh_instanced(
+                mesh,
+                material,
+                draw.instance..draw.instance + 1,
+                bind_group,
+                Some(pipelines),
+            );
+        }
+    }
+}
+
+impl Default for TransparentPhase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,197 @@
+use wgpu::{util::DeviceExt, Device, TextureView};
+
+use crate::graph::{AttachmentDesc, RenderGraph};
+
+use super::{BloomConfig, PostChain, PostEffect};
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    direction: [f32; 2],
+    threshold: f32,
+    intensity: f32,
+}
+
+/// Makes the bright parts of the scene (above `BloomConfig::threshold`) glow
+/// The bright parts are extracted and blurred at half resolution, then added back to the scene
+pub struct Bloom {
+    config: BloomConfig,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    composite_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // Threshold and intensity, then the same along each blur axis
+    uniform_buffers: [wgpu::Buffer; 3],
+}
+
+impl Bloom {
+    pub fn new(device: &Device, format: wgpu::TextureFormat, bloom: &BloomConfig) -> Self {
+        let layout = super::effect_layout(device, "[Bloom] Layout", &[super::uniform_entry(2)]);
+        let composite_layout = super::effect_layout(
+            device,
+            "[Bloom] Composite layout",
+            &[super::uniform_entry(2), super::texture_entry(3)],
+        );
+        let shader = super::effect_shader(
+            device,
+            "[Bloom] Shader",
+            include_str!("../shaders/bloom.wgsl"),
+        );
+        // The bright parts stay in HDR until they are added back
+        let hdr_pipeline = |label, entry_point| {
+            super::effect_pipeline(
+                device,
+                label,
+                &shader,
+                entry_point,
+                &layout,
+                PostChain::HDR_FORMAT,
+            )
+        };
+        let bright_pipeline = hdr_pipeline("[Bloom] Bright pipeline", "fs_bright");
+        let blur_pipeline = hdr_pipeline("[Bloom] Blur pipeline", "fs_blur");
+        let composite_pipeline = super::effect_pipeline(
+            device,
+            "[Bloom] Composite pipeline",
+            &shader,
+            "fs_composite",
+            &composite_layout,
+            format,
+        );
+
+        // The blur directions are axes, the shader scales them to a texel of its input
+        let uniform_buffers = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]].map(|direction| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("[Bloom] Uniform"),
+                contents: bytemuck::cast_slice(&[BloomUniform {
+                    direction,
+                    threshold: bloom.threshold,
+                    intensity: bloom.intensity,
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+        });
+
+        Self {
+            config: *bloom,
+            bright_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            layout,
+            composite_layout,
+            sampler: super::linear_sampler(device, "[Bloom] Sampler"),
+            uniform_buffers,
+        }
+    }
+
+    fn bind_group(
+        &self,
+        device: &Device,
+        input: &TextureView,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        super::effect_bind_group(
+            device,
+            "[Bloom] Bind group",
+            &self.layout,
+            input,
+            &self.sampler,
+            &[wgpu::BindGroupEntry {
+                binding: 2,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        )
+    }
+
+    // A half resolution step of the blur, from `input` into `output`
+    fn add_blur_node<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        label: &str,
+        pipeline: &'a wgpu::RenderPipeline,
+        uniform_buffer: &'a wgpu::Buffer,
+        input: &str,
+        output: &str,
+    ) {
+        graph.add_attachment(
+            output,
+            AttachmentDesc::new(PostChain::HDR_FORMAT).with_downscale(2),
+        );
+        let (label, input_name, output_name) =
+            (label.to_string(), input.to_string(), output.to_string());
+        graph.add_node(label.clone(), &[input], &[output], move |ctx| {
+            let bind_group = self.bind_group(ctx.device, ctx.view(&input_name), uniform_buffer);
+            let output = ctx.view(&output_name);
+            super::draw_fullscreen(ctx.encoder, &label, pipeline, &bind_group, output);
+        });
+    }
+}
+
+impl PostEffect for Bloom {
+    fn add_to_graph<'a>(&'a self, graph: &mut RenderGraph<'a>, input: &str, output: &str) {
+        let [params, horizontal, vertical] = &self.uniform_buffers;
+
+        // Every step writes its own attachment, the pool only allocates two of them
+        let bright = format!("{}.bloom", output);
+        self.add_blur_node(
+            graph,
+            "[Bloom] Bright pass",
+            &self.bright_pipeline,
+            params,
+            input,
+            &bright,
+        );
+        let mut blurred = bright;
+        for index in 0..self.config.passes {
+            for (axis, uniform_buffer) in [("h", horizontal), ("v", vertical)] {
+                let next = format!("{}.bloom.{}.{}", output, index, axis);
+                self.add_blur_node(
+                    graph,
+                    "[Bloom] Blur pass",
+                    &self.blur_pipeline,
+                    uniform_buffer,
+                    &blurred,
+                    &next,
+                );
+                blurred = next;
+            }
+        }
+
+        let (input_name, output_name, bloom_name) =
+            (input.to_string(), output.to_string(), blurred.clone());
+        graph.add_node(
+            "[Bloom] Composite pass",
+            &[input, &blurred],
+            &[output],
+            move |ctx| {
+                let composite = super::effect_bind_group(
+                    ctx.device,
+                    "[Bloom] Composite bind group",
+                    &self.composite_layout,
+                    ctx.view(&input_name),
+                    &self.sampler,
+                    &[
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: params.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(ctx.view(&bloom_name)),
+                        },
+                    ],
+                );
+                let output = ctx.view(&output_name);
+                super::draw_fullscreen(
+                    ctx.encoder,
+                    "[Bloom] Composite pass",
+                    &self.composite_pipeline,
+                    &composite,
+                    output,
+                );
+            },
+        );
+    }
+}
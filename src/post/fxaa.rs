@@ -0,0 +1,61 @@
+use wgpu::Device;
+
+use crate::graph::RenderGraph;
+
+use super::PostEffect;
+
+/// Smooths the aliased edges of the image, cheaper than MSAA but blurrier
+pub struct Fxaa {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl Fxaa {
+    pub fn new(device: &Device, format: wgpu::TextureFormat) -> Self {
+        let layout = super::effect_layout(device, "[FXAA] Layout", &[]);
+        let shader = super::effect_shader(
+            device,
+            "[FXAA] Shader",
+            include_str!("../shaders/fxaa.wgsl"),
+        );
+        let pipeline = super::effect_pipeline(
+            device,
+            "[FXAA] Pipeline",
+            &shader,
+            "fs_main",
+            &layout,
+            format,
+        );
+
+        Self {
+            pipeline,
+            layout,
+            sampler: super::linear_sampler(device, "[FXAA] Sampler"),
+        }
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn add_to_graph<'a>(&'a self, graph: &mut RenderGraph<'a>, input: &str, output: &str) {
+        let (input_name, output_name) = (input.to_string(), output.to_string());
+        graph.add_node("[FXAA] Pass", &[input], &[output], move |ctx| {
+            let bind_group = super::effect_bind_group(
+                ctx.device,
+                "[FXAA] Bind group",
+                &self.layout,
+                ctx.view(&input_name),
+                &self.sampler,
+                &[],
+            );
+            let output = ctx.view(&output_name);
+            super::draw_fullscreen(
+                ctx.encoder,
+                "[FXAA] Pass",
+                &self.pipeline,
+                &bind_group,
+                output,
+            );
+        });
+    }
+}
@@ -0,0 +1,297 @@
+use wgpu::{CommandEncoder, Device, TextureView};
+
+use crate::graph::{self, AttachmentDesc, RenderGraph};
+
+use self::{bloom::Bloom, fxaa::Fxaa, tone_mapping::ToneMapping};
+
+pub mod bloom;
+pub mod fxaa;
+pub mod tone_mapping;
+
+/// A full-screen step of the post chain
+pub trait PostEffect {
+    /// Adds the nodes of the effect, reading the `input` attachment (the scene or the previous
+    /// effect) and writing `output`
+    fn add_to_graph<'a>(&'a self, graph: &mut RenderGraph<'a>, input: &str, output: &str);
+}
+
+// How tone mapping compresses the HDR colors
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum ToneMappingCurve {
+    // Filmic, contrasted with desaturated highlights
+    #[default]
+    Aces,
+    // Softer, never quite reaches white
+    Reinhard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMappingConfig {
+    pub curve: ToneMappingCurve,
+    // Scales the colors before the curve, higher is brighter
+    pub exposure: f32,
+}
+
+impl Default for ToneMappingConfig {
+    fn default() -> Self {
+        Self {
+            curve: ToneMappingCurve::default(),
+            exposure: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BloomConfig {
+    // Brightness (in HDR) above which colors bleed
+    pub threshold: f32,
+    // How much of the blurred bright parts is added back
+    pub intensity: f32,
+    // Blur passes, each one spreads the glow further
+    pub passes: u32,
+}
+
+impl Default for BloomConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.3,
+            passes: 2,
+        }
+    }
+}
+
+// The effects of the post chain, applied in the order they are listed
+// Bloom needs the HDR colors so it comes before tone mapping, FXAA works best after it
+#[derive(Clone, Copy, Debug, PartialEq, serde::Deserialize)]
+pub enum PostEffectConfig {
+    ToneMapping(ToneMappingConfig),
+    Bloom(BloomConfig),
+    Fxaa,
+}
+
+/// The scene is rendered into an HDR attachment (`graph::SCENE`), then each effect reads the
+/// output of the previous one and the last writes into the frame
+/// Without effects, the scene is copied to the frame as is (colors above 1 are clipped)
+pub struct PostChain {
+    effects: Vec<Box<dyn PostEffect>>,
+}
+
+impl PostChain {
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &Device,
+        config: &wgpu::SurfaceConfiguration,
+        effects: &[PostEffectConfig],
+    ) -> Self {
+        let mut effects = effects
+            .iter()
+            .enumerate()
+            .map(|(index, effect)| {
+                // Only the last effect writes into the frame
+                let format = match index + 1 == effects.len() {
+                    true => config.format,
+                    false => Self::HDR_FORMAT,
+                };
+                let effect: Box<dyn PostEffect> = match effect {
+                    PostEffectConfig::ToneMapping(tone_mapping) => {
+                        Box::new(ToneMapping::new(device, format, tone_mapping))
+                    }
+                    PostEffectConfig::Bloom(bloom) => Box::new(Bloom::new(device, format, bloom)),
+                    PostEffectConfig::Fxaa => Box::new(Fxaa::new(device, format)),
+                };
+                effect
+            })
+            .collect::<Vec<_>>();
+        if effects.is_empty() {
+            effects.push(Box::new(ToneMapping::copy(device, config.format)));
+        }
+
+        Self { effects }
+    }
+
+    /// Adds the effects to the graph, from `graph::SCENE` to `graph::FRAME`
+    /// (each intermediate result gets its own attachment, the pool reuses the textures)
+    pub fn add_to_graph<'a>(&'a self, graph: &mut RenderGraph<'a>) {
+        let last = self.effects.len() - 1;
+        let mut input = graph::SCENE.to_string();
+        for (index, effect) in self.effects.iter().enumerate() {
+            let output = match index == last {
+                true => graph::FRAME.to_string(),
+                false => {
+                    let output = format!("post.{}", index);
+                    graph.add_attachment(&output, AttachmentDesc::new(Self::HDR_FORMAT));
+                    output
+                }
+            };
+            effect.add_to_graph(graph, &input, &output);
+            input = output;
+        }
+    }
+}
+
+/// Prepends the full-screen vertex shader and the input bindings (`shaders/post.wgsl`)
+pub(crate) fn effect_shader(device: &Device, label: &str, source: &str) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(
+            format!("{}\n{}", include_str!("../shaders/post.wgsl"), source).into(),
+        ),
+    })
+}
+
+/// Bind group of an effect, matching `effect_layout`
+pub(crate) fn effect_bind_group(
+    device: &Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    input: &TextureView,
+    sampler: &wgpu::Sampler,
+    entries: &[wgpu::BindGroupEntry],
+) -> wgpu::BindGroup {
+    let input = [
+        wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(input),
+        },
+        wgpu::BindGroupEntry {
+            binding: 1,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        },
+    ];
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &input
+            .into_iter()
+            .chain(entries.iter().cloned())
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Layout of an effect: the input texture and its sampler, then the effect's own `entries`
+/// (starting at binding 2)
+pub(crate) fn effect_layout(
+    device: &Device,
+    label: &str,
+    entries: &[wgpu::BindGroupLayoutEntry],
+) -> wgpu::BindGroupLayout {
+    let input = [
+        texture_entry(0),
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &[&input[..], entries].concat(),
+    })
+}
+
+pub(crate) fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+pub(crate) fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Bilinear and clamped, so effects can sample between and past the texels
+pub(crate) fn linear_sampler(device: &Device, label: &str) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(label),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// A pipeline drawing the full-screen triangle with the `entry_point` fragment shader
+pub(crate) fn effect_pipeline(
+    device: &Device,
+    label: &str,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    layout: &wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        primitive: Default::default(),
+        depth_stencil: None,
+        multisample: Default::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Draws the full-screen triangle into `output`, every pixel is overwritten
+pub(crate) fn draw_fullscreen(
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    output: &TextureView,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: output,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,98 @@
+use wgpu::{util::DeviceExt, Device};
+
+use crate::graph::RenderGraph;
+
+use super::{PostEffect, ToneMappingConfig, ToneMappingCurve};
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMappingUniform {
+    exposure: f32,
+    // See `ToneMapping::curve` in `shaders/tone_mapping.wgsl`
+    curve: u32,
+    _padding: [u32; 2],
+}
+
+/// Brings the HDR colors into the displayable range with a curve (`ToneMappingCurve`)
+pub struct ToneMapping {
+    pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl ToneMapping {
+    pub fn new(device: &Device, format: wgpu::TextureFormat, config: &ToneMappingConfig) -> Self {
+        let curve = match config.curve {
+            ToneMappingCurve::Aces => 0,
+            ToneMappingCurve::Reinhard => 1,
+        };
+        Self::with_curve(device, format, config.exposure, curve)
+    }
+
+    /// Copies the colors as they are, only clamping them (for a chain without effects)
+    pub fn copy(device: &Device, format: wgpu::TextureFormat) -> Self {
+        Self::with_curve(device, format, 1.0, 2)
+    }
+
+    fn with_curve(device: &Device, format: wgpu::TextureFormat, exposure: f32, curve: u32) -> Self {
+        let layout =
+            super::effect_layout(device, "[Tone mapping] Layout", &[super::uniform_entry(2)]);
+        let shader = super::effect_shader(
+            device,
+            "[Tone mapping] Shader",
+            include_str!("../shaders/tone_mapping.wgsl"),
+        );
+        let pipeline = super::effect_pipeline(
+            device,
+            "[Tone mapping] Pipeline",
+            &shader,
+            "fs_main",
+            &layout,
+            format,
+        );
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("[Tone mapping] Uniform"),
+            contents: bytemuck::cast_slice(&[ToneMappingUniform {
+                exposure,
+                curve,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            sampler: super::linear_sampler(device, "[Tone mapping] Sampler"),
+            uniform_buffer,
+        }
+    }
+}
+
+impl PostEffect for ToneMapping {
+    fn add_to_graph<'a>(&'a self, graph: &mut RenderGraph<'a>, input: &str, output: &str) {
+        let (input_name, output_name) = (input.to_string(), output.to_string());
+        graph.add_node("[Tone mapping] Pass", &[input], &[output], move |ctx| {
+            let bind_group = super::effect_bind_group(
+                ctx.device,
+                "[Tone mapping] Bind group",
+                &self.layout,
+                ctx.view(&input_name),
+                &self.sampler,
+                &[wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                }],
+            );
+            let output = ctx.view(&output_name);
+            super::draw_fullscreen(
+                ctx.encoder,
+                "[Tone mapping] Pass",
+                &self.pipeline,
+                &bind_group,
+                output,
+            );
+        });
+    }
+}
@@ -7,127 +7,151 @@ pub fn cube_vertices(scale: f32) -> Vec<ModelVertex> {
             position: [-scale, -scale, scale],
             normal: [0.0, 0.0, 1.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, scale],
-            normal: [0.0, 0.0, -1.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, scale],
-            normal: [1.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, scale],
-            normal: [-1.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
         // Back face
         ModelVertex {
             position: [-scale, -scale, -scale],
-            normal: [0.0, 1.0, 0.0],
+            normal: [0.0, 0.0, -1.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, -scale],
-            normal: [0.0, -1.0, 0.0],
+            normal: [0.0, 0.0, -1.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, -scale],
-            normal: [0.0, 0.0, 1.0],
+            normal: [0.0, 0.0, -1.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, -scale],
             normal: [0.0, 0.0, -1.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
         // Top face
         ModelVertex {
             position: [-scale, scale, -scale],
-            normal: [1.0, 0.0, 0.0],
+            normal: [0.0, 1.0, 0.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, scale],
-            normal: [-1.0, 0.0, 0.0],
+            normal: [0.0, 1.0, 0.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, scale],
             normal: [0.0, 1.0, 0.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, -scale],
-            normal: [0.0, -1.0, 0.0],
+            normal: [0.0, 1.0, 0.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
         // Bottom face
         ModelVertex {
             position: [-scale, -scale, -scale],
-            normal: [0.0, 0.0, 1.0],
+            normal: [0.0, -1.0, 0.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, -scale],
-            normal: [0.0, 0.0, -1.0],
+            normal: [0.0, -1.0, 0.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, scale],
-            normal: [1.0, 0.0, 0.0],
+            normal: [0.0, -1.0, 0.0],
 
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, -scale, scale],
-            normal: [-1.0, 0.0, 0.0],
+            normal: [0.0, -1.0, 0.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
         // Right face
         ModelVertex {
             position: [scale, -scale, -scale],
-            normal: [0.0, 1.0, 0.0],
+            normal: [1.0, 0.0, 0.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, -scale],
-            normal: [0.0, -1.0, 0.0],
+            normal: [1.0, 0.0, 0.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, scale],
-            normal: [0.0, 0.0, 1.0],
+            normal: [1.0, 0.0, 0.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, scale],
-            normal: [0.0, 0.0, -1.0],
+            normal: [1.0, 0.0, 0.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
         // Left face
         ModelVertex {
             position: [-scale, -scale, -scale],
-            normal: [1.0, 0.0, 0.0],
+            normal: [-1.0, 0.0, 0.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, -scale, scale],
             normal: [-1.0, 0.0, 0.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, scale],
-            normal: [0.0, 1.0, 0.0],
+            normal: [-1.0, 0.0, 0.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, -scale],
-            normal: [0.0, -1.0, 0.0],
+            normal: [-1.0, 0.0, 0.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
     ]
 }
@@ -1,4 +1,5 @@
 use crate::{
+    bounds::Bounds,
     model::{self, ModelVertex},
     resources::load_texture,
 };
@@ -16,7 +17,7 @@ impl PrimitiveMesh {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         vertices: &[ModelVertex],
-        indices: &Vec<u32>,
+        indices: &[u32],
     ) -> Self {
         let primitive_type = "Cube";
 
@@ -33,23 +34,24 @@ impl PrimitiveMesh {
         .await
         .expect("Couldn't load placeholder texture for primitive");
 
-        materials.push(model::Material {
-            name: primitive_type.to_string(),
-            diffuse_texture,
-        });
+        materials.push(model::Material::new(primitive_type, diffuse_texture));
 
         log::info!("[PRIMITIVE] Creating cube mesh buffers");
         let mut meshes = Vec::new();
 
+        // The generators only give positions, normals and UVs
+        let mut vertices = vertices.to_vec();
+        model::compute_tangents(&mut vertices, indices);
+
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{:?} Vertex Buffer", primitive_type)),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
         });
         let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{:?} Index Buffer", primitive_type)),
             contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
         });
 
         meshes.push(model::Mesh {
@@ -57,7 +59,9 @@ impl PrimitiveMesh {
             vertex_buffer,
             index_buffer,
             num_elements: indices.len() as u32,
+            num_vertices: vertices.len() as u32,
             material: 0,
+            bounds: Bounds::from_vertices(&vertices),
         });
 
         let animations = Vec::new();
@@ -7,21 +7,25 @@ pub fn plane_vertices(scale: f32) -> Vec<ModelVertex> {
             position: [-scale, -scale, scale],
             normal: [0.0, 0.0, 1.0],
             tex_coords: [0.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, -scale, scale],
-            normal: [0.0, 0.0, -1.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [1.0, 0.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [scale, scale, scale],
-            normal: [1.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [1.0, 1.0],
+            ..Default::default()
         },
         ModelVertex {
             position: [-scale, scale, scale],
-            normal: [-1.0, 0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
             tex_coords: [0.0, 1.0],
+            ..Default::default()
         },
     ]
 }
@@ -43,13 +43,14 @@ pub fn generate_sphere(
             nz = z * length_inv;
 
             // Texture coordinates
-            s = (j / sector_count) as f32;
-            t = (i / stack_count) as f32;
+            s = j as f32 / sector_count as f32;
+            t = i as f32 / stack_count as f32;
 
             vertices.push(ModelVertex {
                 position: [x, y, z],
                 normal: [nx, ny, nz],
                 tex_coords: [s, t],
+                ..Default::default()
             })
         }
     }
@@ -63,13 +64,15 @@ pub fn generate_sphere(
     //  |  / |
     //  | /  |
     //  k2--k2+1
-    for i in 0..(stack_count + 1) {
+    // There are `stack_count` strips between the rows of vertices
+    // (iterating one more would index past the last row)
+    for i in 0..stack_count {
         // Top row
         k1 = i * (sector_count + 1);
         // Bottom row
         k2 = k1 + (sector_count + 1);
 
-        for _j in 0..(sector_count + 1) {
+        for _j in 0..sector_count {
             if i != 0 {
                 indices.push(k1);
                 indices.push(k2);
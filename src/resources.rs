@@ -1,12 +1,15 @@
 use std::{
     io::{BufReader, Cursor},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
+use anyhow::Context;
 use gltf::Gltf;
 use wgpu::util::DeviceExt;
 
 use crate::{
+    bounds::Bounds,
+    cubemap,
     model::{self, AnimationClip, Keyframes},
     texture,
 };
@@ -24,6 +27,18 @@ fn format_url(file_name: &Path) -> reqwest::Url {
     base.join(&file_name.display().to_string()).unwrap()
 }
 
+/// Resolves a path relative to the `assets/` folder
+pub fn asset_path(file_name: &Path) -> PathBuf {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        Path::new(FILE).join("assets").join(file_name)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        Path::new("assets").join(file_name)
+    }
+}
+
 pub async fn load_string(file_name: &Path) -> anyhow::Result<String> {
     #[cfg(target_arch = "wasm32")]
     {
@@ -82,15 +97,69 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, &file_name.display().to_string())
 }
 
+/// Loads the six faces of a cubemap (+X, -X, +Y, -Y, +Z, -Z), relative to the `assets/` folder
+pub async fn load_cubemap(
+    faces: &[PathBuf],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let mut images = Vec::with_capacity(faces.len());
+    for face in faces {
+        let path = asset_path(face);
+        let data = load_binary(&path).await?;
+        images.push(
+            image::load_from_memory(&data)
+                .with_context(|| format!("Invalid cubemap face {}", path.display()))?,
+        );
+    }
+    cubemap::from_faces(device, queue, &images, "Cubemap")
+}
+
+/// Loads an equirectangular image (an HDR `.hdr` or any other format) as a cubemap,
+/// relative to the `assets/` folder
+pub async fn load_equirectangular(
+    file_name: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let path = asset_path(file_name);
+
+    log::info!("Loading environment: {}", path.display());
+    let data = load_binary(&path).await?;
+    let image = if path.extension() == Some("hdr".as_ref()) {
+        // The generic loader would bring the colors down to 8 bits
+        load_hdr(&data).with_context(|| format!("Invalid HDR image {}", path.display()))?
+    } else {
+        image::load_from_memory(&data)
+            .with_context(|| format!("Invalid environment image {}", path.display()))?
+    };
+    Ok(cubemap::from_equirectangular(
+        device,
+        queue,
+        &image,
+        &path.display().to_string(),
+    ))
+}
+
+fn load_hdr(data: &[u8]) -> anyhow::Result<image::DynamicImage> {
+    let decoder = image::codecs::hdr::HdrDecoder::new(data)?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+    let image = image::Rgb32FImage::from_raw(
+        metadata.width,
+        metadata.height,
+        pixels.iter().flat_map(|pixel| pixel.0).collect(),
+    )
+    .context("The HDR image is smaller than its header says")?;
+    Ok(image::DynamicImage::ImageRgb32F(image))
+}
+
 pub async fn load_model(
     file_name: &Path,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
 ) -> anyhow::Result<model::Model> {
-    #[cfg(not(target_arch = "wasm32"))]
-    let file_name = std::path::Path::new(FILE).join("assets").join(file_name);
-    #[cfg(target_arch = "wasm32")]
-    let file_name = std::path::Path::new("assets").join(file_name);
+    let file_name = asset_path(file_name);
 
     log::info!("Loading model: {}", file_name.display());
     if file_name.extension() == Some("obj".as_ref()) {
@@ -130,19 +199,71 @@ pub async fn load_model_obj(
 
     let mut materials = Vec::new();
     for m in obj_materials? {
-        let diffuse_texture = file_name.with_file_name(m.diffuse_texture);
-        let diffuse_texture = load_texture(&diffuse_texture, device, queue).await?;
+        // The color map replaces `Kd` (exporters often leave it black next to a map)
+        let (diffuse_texture, diffuse) = if m.diffuse_texture.is_empty() {
+            let white = texture::Texture::from_color(device, queue, [255; 4], &m.name);
+            (white, m.diffuse)
+        } else {
+            let path = file_name.with_file_name(&m.diffuse_texture);
+            (load_texture(&path, device, queue).await?, [1.0; 3])
+        };
+        let specular_texture = if m.specular_texture.is_empty() {
+            None
+        } else {
+            let path = file_name.with_file_name(&m.specular_texture);
+            Some(load_texture(&path, device, queue).await?)
+        };
+
+        // Shininess (Ns) maps to roughness the same way Blinn-Phong maps to GGX
+        let roughness = (2.0 / (m.shininess + 2.0)).sqrt();
+        // `map_Bump` (or `bump`) is expected to be a tangent space normal map
+        let normal_texture = if m.normal_texture.is_empty() {
+            None
+        } else {
+            let path = file_name.with_file_name(&m.normal_texture);
+            let data = load_binary(&path).await?;
+            let label = path.display().to_string();
+            Some(
+                texture::Texture::from_bytes_linear(device, queue, &data, &label)
+                    .with_context(|| format!("Couldn't load {}", label))?,
+            )
+        };
+        // tobj doesn't know about `Ke`
+        let emissive = m
+            .unknown_param
+            .get("Ke")
+            .and_then(|ke| {
+                let ke = ke
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<f32>, _>>()
+                    .ok()?;
+                Some([*ke.first()?, *ke.get(1)?, *ke.get(2)?])
+            })
+            .unwrap_or([0.0; 3]);
 
         materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
+            base_color_factor: [diffuse[0], diffuse[1], diffuse[2], m.dissolve],
+            specular_color: m.specular,
+            shininess: m.shininess,
+            roughness_factor: roughness,
+            emissive_factor: emissive,
+            // `d` below 1 makes the whole material see-through
+            alpha_mode: if m.dissolve < 1.0 {
+                model::AlphaMode::Blend
+            } else {
+                model::AlphaMode::Opaque
+            },
+            normal_texture,
+            specular_texture,
+            ..model::Material::new(m.name, diffuse_texture)
         })
     }
 
     let meshes = models
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
+            let mut vertices = (0..m.mesh.positions.len() / 3)
                 .map(|i| model::ModelVertex {
                     position: [
                         m.mesh.positions[i * 3],
@@ -155,18 +276,21 @@ pub async fn load_model_obj(
                         m.mesh.normals[i * 3 + 1],
                         m.mesh.normals[i * 3 + 2],
                     ],
+                    tangent: [0.0; 4],
                 })
                 .collect::<Vec<_>>();
+            // OBJ files have no tangents
+            model::compute_tangents(&mut vertices, &m.mesh.indices);
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
                 contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             });
 
             model::Mesh {
@@ -174,18 +298,22 @@ pub async fn load_model_obj(
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+                num_vertices: vertices.len() as u32,
+                material: m.mesh.material_id.unwrap_or(materials.len()),
+                bounds: Bounds::from_vertices(&vertices),
             }
         })
         .collect::<Vec<_>>();
 
     let animations = Vec::new();
 
-    Ok(model::Model {
+    let mut model = model::Model {
         meshes,
         materials,
         animations,
-    })
+    };
+    model.add_default_material(device, queue);
+    Ok(model)
 }
 
 pub async fn load_model_gltf(
@@ -198,172 +326,7 @@ pub async fn load_model_gltf(
     let gltf_reader = BufReader::new(gltf_cursor);
     let gltf = Gltf::from_reader(gltf_reader)?;
 
-    // Load buffers
-    let mut buffer_data = Vec::new();
-    for buffer in gltf.buffers() {
-        let bin = match buffer.source() {
-            gltf::buffer::Source::Bin => {
-                // if let Some(blob) = gltf.blob.as_deref() {
-                //     buffer_data.push(blob.into());
-                //     println!("Found a bin, saving");
-                // };
-                Vec::new()
-            }
-            gltf::buffer::Source::Uri(uri) => {
-                let uri = file_name.with_file_name(uri);
-                load_binary(&uri).await?
-            }
-        };
-
-        buffer_data.push(bin);
-    }
-
-    log::debug!("Initizalized buffers");
-
-    // Load animations
-    let mut animation_clips = Vec::new();
-    for animation in gltf.animations() {
-        for channel in animation.channels() {
-            let reader = channel.reader(|buffer| Some(&buffer_data[buffer.index()]));
-            let timestamps = if let Some(inputs) = reader.read_inputs() {
-                match inputs {
-                    gltf::accessor::Iter::Standard(times) => {
-                        let times: Vec<f32> = times.collect();
-                        times
-                    }
-                    gltf::accessor::Iter::Sparse(_) => {
-                        log::error!("Sparse keyframes not supported");
-                        Vec::new()
-                    }
-                }
-            } else {
-                log::error!("Couldn't read inputs");
-                Vec::new()
-            };
-
-            let keyframes = if let Some(outputs) = reader.read_outputs() {
-                match outputs {
-                    gltf::animation::util::ReadOutputs::Translations(translation) => {
-                        let translation_vec = translation
-                            .map(|tr| {
-                                let vector: Vec<f32> = tr.into();
-                                vector
-                            })
-                            .collect();
-                        Keyframes::Translation(translation_vec)
-                    }
-                    _ => todo!(),
-                }
-            } else {
-                log::error!("Couldn't read outputs");
-                Keyframes::Other
-            };
-
-            animation_clips.push(AnimationClip {
-                name: animation.name().unwrap_or("Default").to_string(),
-                keyframes,
-                timestamps,
-            })
-        }
-    }
-
-    // Load materials
-    let mut materials = Vec::new();
-    log::info!("Looping through materials");
-    for material in gltf.materials() {
-        let pbr = material.pbr_metallic_roughness();
-        let texture_source = &pbr
-            .base_color_texture()
-            .map(|tex| tex.texture().source().source())
-            .expect("texture");
-
-        let diffuse_texture = match texture_source {
-            gltf::image::Source::View { view, .. } => texture::Texture::from_bytes(
-                device,
-                queue,
-                &buffer_data[view.buffer().index()],
-                &file_name.display().to_string(),
-            )
-            .expect("Couldn't load diffuse"),
-            gltf::image::Source::Uri { uri, .. } => {
-                let uri = file_name.with_file_name(uri);
-                load_texture(&uri, device, queue).await?
-            }
-        };
-
-        materials.push(model::Material {
-            name: material.name().unwrap_or("Default Material").to_string(),
-            diffuse_texture,
-        });
-    }
-
-    let mut meshes = Vec::new();
-
-    for scene in gltf.scenes() {
-        for node in scene.nodes() {
-            log::info!("Node {} {}", node.index(), node.name().unwrap_or("Unnamed"));
-
-            let mesh = node.mesh().expect("Got mesh");
-            let primitives = mesh.primitives();
-            primitives.for_each(|primitive| {
-                let reader = primitive.reader(|buffer| Some(&buffer_data[buffer.index()]));
-
-                log::info!("[START] Reading positions, normals, tex_coords");
-                let (positions, normals, tex_coords) = (
-                    reader.read_positions().unwrap(),
-                    reader.read_normals().unwrap(),
-                    reader.read_tex_coords(0).unwrap().into_f32(),
-                );
-                log::info!("[END  ] Reading positions, normals, tex_coords");
-
-                log::info!("[START] Reading indices");
-                let indices = reader.read_indices().map(|indices| indices.into_u32());
-                let indices = match indices {
-                    Some(indices) => indices.collect::<Vec<_>>(),
-                    None => (0..positions.len() as u32).collect(),
-                };
-                log::info!("[END  ] Reading indices");
-
-                let vertices = positions
-                    .zip(normals)
-                    .zip(tex_coords)
-                    .map(|((position, normal), tex_coords)| model::ModelVertex {
-                        position,
-                        normal,
-                        tex_coords,
-                    })
-                    .collect::<Vec<_>>();
-
-                log::info!("[START] Creating buffers");
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("{:?} Index Buffer", file_name)),
-                    contents: bytemuck::cast_slice(&indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-                log::info!("[END  ] Creating buffers");
-
-                meshes.push(model::Mesh {
-                    name: file_name.display().to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements: indices.len() as u32,
-                    // material: m.mesh.material_id.unwrap_or(0),
-                    material: 0,
-                });
-            });
-        }
-    }
-
-    Ok(model::Model {
-        meshes,
-        materials,
-        animations: animation_clips,
-    })
+    load_gltf(gltf, file_name, device, queue).await
 }
 
 pub async fn load_model_glb(
@@ -376,18 +339,29 @@ pub async fn load_model_glb(
     let gltf_reader = BufReader::new(gltf_cursor);
     let gltf = Gltf::from_reader(gltf_reader)?;
 
+    load_gltf(gltf, file_name, device, queue).await
+}
+
+// Builds the model of a parsed glTF or GLB file (the binary chunk is the GLB `blob`)
+async fn load_gltf(
+    gltf: Gltf,
+    file_name: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<model::Model> {
+    let Gltf { document, blob } = gltf;
+
     // Load buffers
     let mut buffer_data = Vec::new();
-    for buffer in gltf.buffers() {
+    for buffer in document.buffers() {
         let bin = match buffer.source() {
-            gltf::buffer::Source::Bin => {
-                if let Some(blob) = gltf.blob.clone() {
-                    blob
-                } else {
+            gltf::buffer::Source::Bin => match &blob {
+                Some(blob) => blob.clone(),
+                None => {
                     log::error!("Missing blob");
                     return Err(anyhow::anyhow!("Missing blob"));
                 }
-            }
+            },
             gltf::buffer::Source::Uri(uri) => {
                 let uri = file_name.with_file_name(uri);
                 load_binary(&uri).await?
@@ -401,7 +375,7 @@ pub async fn load_model_glb(
 
     // Load animations
     let mut animation_clips = Vec::new();
-    for animation in gltf.animations() {
+    for animation in document.animations() {
         for channel in animation.channels() {
             let reader = channel.reader(|buffer| Some(&buffer_data[buffer.index()]));
             let timestamps = if let Some(inputs) = reader.read_inputs() {
@@ -431,7 +405,15 @@ pub async fn load_model_glb(
                             .collect();
                         Keyframes::Translation(translation_vec)
                     }
-                    _ => todo!(),
+                    // Only translations are played (see `Engine::update`)
+                    _ => {
+                        log::warn!(
+                            "Skipping the {:?} channel of animation {:?}, only translations are supported",
+                            channel.target().property(),
+                            animation.name().unwrap_or("Default")
+                        );
+                        continue;
+                    }
                 }
             } else {
                 log::error!("Couldn't read outputs");
@@ -447,53 +429,10 @@ pub async fn load_model_glb(
     }
 
     // Load materials
-    let mut materials = Vec::new();
-    log::info!("Looping through materials");
-    for material in gltf.materials() {
-        log::info!(
-            r#"Material#{:?} "{}""#,
-            material.index().map(|f| f as isize).unwrap_or(-1),
-            material.name().unwrap_or("Unnamed")
-        );
-        let pbr = material.pbr_metallic_roughness();
-        let texture_source = &pbr
-            .base_color_texture()
-            .map(|tex| tex.texture().source().source())
-            .expect("texture");
-
-        let diffuse_texture = match texture_source {
-            gltf::image::Source::View { view, .. } => {
-                let start = view.offset();
-                let end = view.offset() + view.length();
-                let buffer = &buffer_data[view.buffer().index()][start..end];
-                //
-                let texture = texture::Texture::from_bytes(
-                    device,
-                    queue,
-                    buffer,
-                    &file_name.display().to_string(),
-                );
-                let Ok(texture) = texture else {
-                    log::error!("Couldn't load texture from buffer#{}: {}",view.buffer().index(), texture.err().unwrap());
-                    continue;
-                };
-
-                texture
-            }
-            gltf::image::Source::Uri { uri, .. } => {
-                let uri = file_name.with_file_name(uri);
-                load_texture(&uri, device, queue).await?
-            }
-        };
-
-        materials.push(model::Material {
-            name: material.name().unwrap_or("Default Material").to_string(),
-            diffuse_texture,
-        });
-    }
+    let materials = load_gltf_materials(&document, &buffer_data, file_name, device, queue).await?;
 
     let mut meshes = Vec::new();
-    for mesh in gltf.meshes() {
+    for mesh in document.meshes() {
         log::info!(
             r#"Mesh#{} "{}""#,
             mesh.index(),
@@ -520,26 +459,40 @@ pub async fn load_model_glb(
             };
             log::info!("[END  ] Reading indices");
 
-            let vertices = positions
+            let tangents = reader.read_tangents();
+            let has_tangents = tangents.is_some();
+            let mut vertices = positions
                 .zip(normals)
                 .zip(tex_coords)
-                .map(|((position, normal), tex_coords)| model::ModelVertex {
-                    position,
-                    normal,
-                    tex_coords,
-                })
+                .zip(
+                    tangents
+                        .into_iter()
+                        .flatten()
+                        .chain(std::iter::repeat([0.0; 4])),
+                )
+                .map(
+                    |(((position, normal), tex_coords), tangent)| model::ModelVertex {
+                        position,
+                        normal,
+                        tex_coords,
+                        tangent,
+                    },
+                )
                 .collect::<Vec<_>>();
+            if !has_tangents {
+                model::compute_tangents(&mut vertices, &indices);
+            }
 
             log::info!("[START] Creating buffers");
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
                 contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
                 contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_SRC,
             });
             log::info!("[END  ] Creating buffers");
 
@@ -548,14 +501,146 @@ pub async fn load_model_glb(
                 vertex_buffer,
                 index_buffer,
                 num_elements: indices.len() as u32,
-                material: primitive.material().index().unwrap_or(0),
+                num_vertices: vertices.len() as u32,
+                material: primitive.material().index().unwrap_or(materials.len()),
+                bounds: Bounds::from_vertices(&vertices),
             });
         });
     }
 
-    Ok(model::Model {
+    let mut model = model::Model {
         meshes,
         materials,
         animations: animation_clips,
-    })
+    };
+    model.add_default_material(device, queue);
+    Ok(model)
+}
+
+// Reads the metallic-roughness data of every material,
+// missing textures are left to the passes (they only multiply the factors)
+async fn load_gltf_materials(
+    document: &gltf::Document,
+    buffer_data: &[Vec<u8>],
+    file_name: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Vec<model::Material>> {
+    let mut materials = Vec::new();
+    log::info!("Looping through materials");
+    for material in document.materials() {
+        log::info!(
+            r#"Material#{:?} "{}""#,
+            material.index().map(|f| f as isize).unwrap_or(-1),
+            material.name().unwrap_or("Unnamed")
+        );
+        let pbr = material.pbr_metallic_roughness();
+        let base_color = pbr.base_color_texture().map(|info| info.texture());
+        let metallic_roughness = pbr.metallic_roughness_texture().map(|info| info.texture());
+        let occlusion = material.occlusion_texture().map(|info| info.texture());
+        let emissive = material.emissive_texture().map(|info| info.texture());
+        let normal = material.normal_texture();
+
+        let diffuse_texture = match base_color {
+            Some(texture) => {
+                load_gltf_texture(texture, true, buffer_data, file_name, device, queue).await?
+            }
+            None => texture::Texture::from_color(device, queue, [255; 4], "White"),
+        };
+        let metallic_roughness_texture = load_gltf_map(
+            metallic_roughness,
+            false,
+            buffer_data,
+            file_name,
+            device,
+            queue,
+        )
+        .await?;
+        let occlusion_texture =
+            load_gltf_map(occlusion, false, buffer_data, file_name, device, queue).await?;
+        let emissive_texture =
+            load_gltf_map(emissive, true, buffer_data, file_name, device, queue).await?;
+        let normal_scale = normal.as_ref().map_or(1.0, |normal| normal.scale());
+        let normal_texture = load_gltf_map(
+            normal.map(|normal| normal.texture()),
+            false,
+            buffer_data,
+            file_name,
+            device,
+            queue,
+        )
+        .await?;
+
+        materials.push(model::Material {
+            base_color_factor: pbr.base_color_factor(),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            emissive_factor: material.emissive_factor(),
+            occlusion_strength: material
+                .occlusion_texture()
+                .map_or(1.0, |occlusion| occlusion.strength()),
+            metallic_roughness_texture,
+            occlusion_texture,
+            emissive_texture,
+            normal_texture,
+            normal_scale,
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => model::AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => model::AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => model::AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            double_sided: material.double_sided(),
+            ..model::Material::new(
+                material.name().unwrap_or("Default Material"),
+                diffuse_texture,
+            )
+        });
+    }
+
+    Ok(materials)
+}
+
+// An optional texture of a material (absent maps stay None)
+async fn load_gltf_map(
+    texture: Option<gltf::Texture<'_>>,
+    srgb: bool,
+    buffer_data: &[Vec<u8>],
+    file_name: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<Option<texture::Texture>> {
+    match texture {
+        Some(texture) => Ok(Some(
+            load_gltf_texture(texture, srgb, buffer_data, file_name, device, queue).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// Colors (`srgb`) are decoded to linear when sampled, other data is read as is
+async fn load_gltf_texture(
+    texture: gltf::Texture<'_>,
+    srgb: bool,
+    buffer_data: &[Vec<u8>],
+    file_name: &Path,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let bytes = match texture.source().source() {
+        gltf::image::Source::View { view, .. } => {
+            let start = view.offset();
+            let end = view.offset() + view.length();
+            buffer_data[view.buffer().index()][start..end].to_vec()
+        }
+        gltf::image::Source::Uri { uri, .. } => load_binary(&file_name.with_file_name(uri)).await?,
+    };
+
+    let label = format!("{} (texture #{})", file_name.display(), texture.index());
+    let texture = if srgb {
+        texture::Texture::from_bytes(device, queue, &bytes, &label)
+    } else {
+        texture::Texture::from_bytes_linear(device, queue, &bytes, &label)
+    };
+    texture.with_context(|| format!("Couldn't load {}", label))
 }
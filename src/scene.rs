@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use crate::{
+    camera::Camera,
+    instance::Instance,
+    light::Light,
+    model::Model,
+    node::{Node, NodeId, Nodes, Transform},
+    particle::{
+        ParticleAppearance, ParticleEmitter, ParticleForces, ParticleSimulation, ParticleSystem,
+    },
+    pass::lights::LightUniform,
+    primitives::{
+        cube::{cube_indices, cube_vertices},
+        plane::{plane_indices, plane_vertices},
+        sphere::generate_sphere,
+        PrimitiveMesh,
+    },
+    resources, texture,
+};
+
+// Everything the engine renders and updates each frame
+// This is what update callbacks receive to mutate the world
+pub struct Scene {
+    pub camera: Camera,
+    // Only the first `PhongConfig::max_lights` are rendered
+    pub lights: Vec<Light>,
+    // The 3D models in the scene (as Nodes)
+    // Nodes can be inserted and removed at any time, the GPU side follows on the next update
+    pub nodes: Nodes,
+    pub particle_systems: Vec<ParticleSystem>,
+}
+
+impl Scene {
+    /// Propagates transforms down the scene graph
+    /// Returns the nodes whose world matrix changed since the last call
+    pub fn update_transforms(&mut self) -> Vec<NodeId> {
+        self.nodes.update_world_matrices()
+    }
+
+    /// The lights as they should be uploaded, in world space
+    pub fn light_uniforms(&self) -> Vec<LightUniform> {
+        self.lights
+            .iter()
+            .map(|light| light.to_uniform(&self.nodes, &self.camera))
+            .collect()
+    }
+}
+
+/// Where the vertex data of a model comes from
+/// Files are resolved relative to the `assets/` folder
+#[derive(Clone, Debug, serde::Deserialize)]
+pub enum ModelSource {
+    File(PathBuf),
+    Sphere {
+        radius: f32,
+        sectors: u32,
+        stacks: u32,
+    },
+    Cube {
+        scale: f32,
+    },
+    Plane {
+        scale: f32,
+    },
+}
+
+impl ModelSource {
+    pub async fn load(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Model> {
+        let (vertices, indices) = match self {
+            ModelSource::File(path) => return resources::load_model(path, device, queue).await,
+            ModelSource::Sphere {
+                radius,
+                sectors,
+                stacks,
+            } => generate_sphere(*radius, *sectors, *stacks),
+            ModelSource::Cube { scale } => (cube_vertices(*scale), cube_indices()),
+            ModelSource::Plane { scale } => (plane_vertices(*scale), plane_indices()),
+        };
+
+        Ok(PrimitiveMesh::new(device, queue, &vertices, &indices)
+            .await
+            .model)
+    }
+}
+
+/// Where the cubemap drawn behind the scene comes from
+/// Files are resolved relative to the `assets/` folder
+#[derive(Clone, Debug, serde::Deserialize)]
+pub enum SkyboxSource {
+    // Six square images, in the order +X, -X, +Y, -Y, +Z, -Z
+    Faces([PathBuf; 6]),
+    // A single latitude/longitude image, usually HDR (`.hdr`)
+    Equirectangular(PathBuf),
+}
+
+impl SkyboxSource {
+    pub async fn load(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<texture::Texture> {
+        match self {
+            SkyboxSource::Faces(faces) => resources::load_cubemap(faces, device, queue).await,
+            SkyboxSource::Equirectangular(path) => {
+                resources::load_equirectangular(path, device, queue).await
+            }
+        }
+    }
+}
+
+/// Description of a node, turned into a `Node` once the GPU is ready
+#[derive(Clone, Debug)]
+pub struct NodeDescriptor {
+    pub model: ModelSource,
+    pub transform: Transform,
+    // Index of the parent in the order nodes were added
+    // (resolved to a `NodeId` when the engine is built)
+    pub parent: Option<usize>,
+    pub instances: Vec<Instance>,
+}
+
+impl NodeDescriptor {
+    pub async fn build(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Node> {
+        let model = self.model.load(device, queue).await?;
+        Ok(Node::new(model, self.transform, self.instances.clone()))
+    }
+}
+
+/// Description of a light, its parent is resolved once the nodes are loaded
+#[derive(Clone, Copy, Debug)]
+pub struct LightDescriptor {
+    pub light: Light,
+    // Index of the node to attach to, in the order nodes were added
+    pub parent: Option<usize>,
+}
+
+/// Description of a particle system, turned into a `ParticleSystem` once the GPU is ready
+#[derive(Clone, Debug, Default)]
+pub struct ParticleSystemDescriptor {
+    // Relative to the `assets/` folder, particles are soft discs without one
+    pub texture: Option<PathBuf>,
+    pub emitter: ParticleEmitter,
+    pub appearance: ParticleAppearance,
+    pub forces: ParticleForces,
+    // Most particles alive at once
+    pub max_particles: u32,
+    // Index of the node the emitter follows, in the order nodes were added
+    pub parent: Option<usize>,
+}
+
+impl ParticleSystemDescriptor {
+    pub async fn build(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation: Option<&ParticleSimulation>,
+    ) -> anyhow::Result<ParticleSystem> {
+        let texture = match &self.texture {
+            Some(path) => {
+                Some(resources::load_texture(&resources::asset_path(path), device, queue).await?)
+            }
+            None => None,
+        };
+        Ok(ParticleSystem::new(
+            device,
+            queue,
+            simulation,
+            texture,
+            self.emitter.clone(),
+            self.appearance.clone(),
+            self.forces,
+            self.max_particles,
+        ))
+    }
+}
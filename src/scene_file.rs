@@ -0,0 +1,549 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, ensure, Context};
+use cgmath::{Deg, Euler, Quaternion};
+use serde::Deserialize;
+
+use crate::{
+    camera::Camera,
+    instance::Instance,
+    light::{Light, LightKind, ShadowConfig},
+    node::Transform,
+    pass::phong::PhongConfig,
+    resources,
+    scene::{LightDescriptor, ModelSource, NodeDescriptor, SkyboxSource},
+};
+
+/// A scene described in a RON (or JSON) file, ready to be added to an `EngineBuilder`
+///
+/// ```ron
+/// (
+///     camera: (position: (0.0, 5.0, 10.0), yaw: -90.0, pitch: -20.0),
+///     phong: (shading: Pbr, wireframe: false),
+///     skybox: Equirectangular("skybox/sky.hdr"),
+///     lights: [
+///         (position: (2.0, 2.0, 2.0), intensity: 10.0, range: 20.0),
+///         (kind: Directional, direction: (-1.0, -1.0, 0.0), color: (1.0, 0.9, 0.8)),
+///         (kind: Spot(inner_angle: 20.0, outer_angle: 30.0), position: (0.0, 4.0, 0.0), shadow: ()),
+///     ],
+///     nodes: [
+///         (
+///             name: "ferris",
+///             model: File("ferris/ferris.obj"),
+///             transform: (position: (0.0, 0.0, -0.2)),
+///             instances: [(position: (0.3, 1.2, -0.2), scale: (0.5, 0.5, 0.5))],
+///         ),
+///         (model: Sphere(radius: 0.5, sectors: 36, stacks: 18), parent: "ferris"),
+///     ],
+/// )
+/// ```
+///
+/// Rotations and angles are in degrees, parents are referenced by name
+/// and a node without instances is drawn once where its transform puts it.
+pub struct SceneFile {
+    pub camera: Option<Camera>,
+    pub phong_config: Option<PhongConfig>,
+    pub skybox: Option<SkyboxSource>,
+    // Parents are indices in `nodes`
+    pub lights: Vec<LightDescriptor>,
+    // Parents are indices in this list
+    pub nodes: Vec<NodeDescriptor>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneFormat {
+    Ron,
+    Json,
+}
+
+impl SceneFormat {
+    /// `.json` files are JSON, everything else is RON
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "json" => SceneFormat::Json,
+            _ => SceneFormat::Ron,
+        }
+    }
+}
+
+impl SceneFile {
+    /// Loads a scene file, the path is relative to the `assets/` folder
+    pub async fn load(path: impl AsRef<Path>) -> anyhow::Result<SceneFile> {
+        let path = resources::asset_path(path.as_ref());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        ensure!(
+            path.exists(),
+            "Scene file does not exist: {}",
+            path.display()
+        );
+
+        log::info!("Loading scene: {}", path.display());
+        let contents = resources::load_string(&path).await?;
+        Self::parse(&contents, SceneFormat::from_path(&path))
+            .with_context(|| format!("Invalid scene file {}", path.display()))
+    }
+
+    pub fn parse(contents: &str, format: SceneFormat) -> anyhow::Result<SceneFile> {
+        let raw: RawScene = match format {
+            // Optional fields can be written without `Some(...)`
+            SceneFormat::Ron => ron::Options::default()
+                .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+                .from_str(contents)?,
+            SceneFormat::Json => serde_json::from_str(contents)?,
+        };
+
+        raw.resolve()
+    }
+}
+
+// What the file contains, before names are resolved to node indices
+
+#[derive(Deserialize)]
+#[serde(rename = "Scene", deny_unknown_fields)]
+struct RawScene {
+    #[serde(default)]
+    camera: Option<RawCamera>,
+    #[serde(default)]
+    phong: Option<PhongConfig>,
+    #[serde(default)]
+    skybox: Option<SkyboxSource>,
+    #[serde(default)]
+    lights: Vec<RawLight>,
+    #[serde(default)]
+    nodes: Vec<RawNode>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Camera", deny_unknown_fields)]
+struct RawCamera {
+    position: [f32; 3],
+    // Degrees
+    yaw: f32,
+    pitch: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Light", deny_unknown_fields)]
+struct RawLight {
+    #[serde(default)]
+    kind: RawLightKind,
+    #[serde(default)]
+    position: [f32; 3],
+    #[serde(default = "down")]
+    direction: [f32; 3],
+    #[serde(default = "white")]
+    color: [f32; 3],
+    #[serde(default = "one")]
+    intensity: f32,
+    // Infinite when not set
+    #[serde(default)]
+    range: Option<f32>,
+    // Name of the node the light follows
+    #[serde(default)]
+    parent: Option<String>,
+    // Casts shadows when set (`shadow: ()` for the default settings)
+    #[serde(default)]
+    shadow: Option<ShadowConfig>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename = "LightKind")]
+enum RawLightKind {
+    Directional,
+    #[default]
+    Point,
+    // Angles in degrees
+    Spot {
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Node", deny_unknown_fields)]
+struct RawNode {
+    #[serde(default)]
+    name: Option<String>,
+    model: ModelSource,
+    #[serde(default)]
+    transform: RawTransform,
+    // Name of the parent node
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    instances: Vec<RawTransform>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Transform", default, deny_unknown_fields)]
+struct RawTransform {
+    position: [f32; 3],
+    // Euler angles in degrees
+    rotation: [f32; 3],
+    scale: [f32; 3],
+}
+
+impl Default for RawTransform {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn down() -> [f32; 3] {
+    [0.0, -1.0, 0.0]
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+impl RawTransform {
+    fn rotation(&self) -> Quaternion<f32> {
+        let [x, y, z] = self.rotation;
+        Quaternion::from(Euler::new(Deg(x), Deg(y), Deg(z)))
+    }
+
+    fn to_transform(&self) -> Transform {
+        Transform {
+            position: self.position.into(),
+            rotation: self.rotation(),
+            scale: self.scale.into(),
+        }
+    }
+
+    fn to_instance(&self) -> Instance {
+        Instance {
+            position: self.position.into(),
+            rotation: self.rotation(),
+            scale: self.scale.into(),
+        }
+    }
+}
+
+impl RawScene {
+    fn resolve(self) -> anyhow::Result<SceneFile> {
+        let mut names = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if let Some(name) = &node.name {
+                if names.insert(name.as_str(), index).is_some() {
+                    bail!("Two nodes are named `{}`", name);
+                }
+            }
+        }
+        let find = |name: &str| names.get(name).copied();
+        let node_label = |index: usize| match &self.nodes[index].name {
+            Some(name) => name.clone(),
+            None => format!("#{}", index),
+        };
+
+        let mut nodes = Vec::with_capacity(self.nodes.len());
+        for (index, node) in self.nodes.iter().enumerate() {
+            let label = node_label(index);
+
+            if let ModelSource::File(path) = &node.model {
+                let supported = ["obj", "gltf", "glb"]
+                    .iter()
+                    .any(|extension| path.extension() == Some(extension.as_ref()));
+                ensure!(
+                    supported,
+                    "Node {}: unsupported model format {} (expected obj, gltf or glb)",
+                    label,
+                    path.display()
+                );
+                #[cfg(not(target_arch = "wasm32"))]
+                ensure!(
+                    resources::asset_path(path).exists(),
+                    "Node {}: model file does not exist: {}",
+                    label,
+                    resources::asset_path(path).display()
+                );
+            }
+
+            let parent =
+                match &node.parent {
+                    Some(parent) => Some(find(parent).with_context(|| {
+                        format!("Node {}: no node is named `{}`", label, parent)
+                    })?),
+                    None => None,
+                };
+            ensure!(parent != Some(index), "Node {} is its own parent", label);
+
+            let instances = if node.instances.is_empty() {
+                vec![RawTransform::default().to_instance()]
+            } else {
+                node.instances
+                    .iter()
+                    .map(RawTransform::to_instance)
+                    .collect()
+            };
+
+            nodes.push(NodeDescriptor {
+                model: node.model.clone(),
+                transform: node.transform.to_transform(),
+                parent,
+                instances,
+            });
+        }
+
+        // Walk up from every node, coming back to a node of the chain means it loops
+        for (index, node) in nodes.iter().enumerate() {
+            let mut chain = vec![index];
+            let mut parent = node.parent;
+            while let Some(current) = parent {
+                if let Some(start) = chain.iter().position(|&other| other == current) {
+                    let cycle = chain[start..]
+                        .iter()
+                        .chain([&current])
+                        .map(|&other| node_label(other))
+                        .collect::<Vec<_>>();
+                    bail!("Nodes form a parent cycle: {}", cycle.join(" -> "));
+                }
+                chain.push(current);
+                parent = nodes[current].parent;
+            }
+        }
+
+        let mut lights = Vec::with_capacity(self.lights.len());
+        for (index, light) in self.lights.iter().enumerate() {
+            let parent =
+                match &light.parent {
+                    Some(parent) => Some(find(parent).with_context(|| {
+                        format!("Light #{}: no node is named `{}`", index, parent)
+                    })?),
+                    None => None,
+                };
+            let kind = match light.kind {
+                RawLightKind::Directional => LightKind::Directional,
+                RawLightKind::Point => LightKind::Point,
+                RawLightKind::Spot {
+                    inner_angle,
+                    outer_angle,
+                } => {
+                    ensure!(
+                        inner_angle <= outer_angle,
+                        "Light #{}: the inner angle ({}°) is wider than the outer one ({}°)",
+                        index,
+                        inner_angle,
+                        outer_angle
+                    );
+                    LightKind::Spot {
+                        inner_angle: Deg(inner_angle).into(),
+                        outer_angle: Deg(outer_angle).into(),
+                    }
+                }
+            };
+            lights.push(LightDescriptor {
+                light: Light {
+                    kind,
+                    direction: light.direction,
+                    intensity: light.intensity,
+                    range: light.range.unwrap_or(f32::INFINITY),
+                    shadow: light.shadow,
+                    ..Light::new(light.position, light.color)
+                },
+                parent,
+            });
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(skybox) = &self.skybox {
+            let paths = match skybox {
+                SkyboxSource::Faces(faces) => &faces[..],
+                SkyboxSource::Equirectangular(path) => std::slice::from_ref(path),
+            };
+            for path in paths {
+                ensure!(
+                    resources::asset_path(path).exists(),
+                    "Skybox file does not exist: {}",
+                    resources::asset_path(path).display()
+                );
+            }
+        }
+
+        let camera = self
+            .camera
+            .map(|camera| Camera::new(camera.position, Deg(camera.yaw), Deg(camera.pitch)));
+
+        Ok(SceneFile {
+            camera,
+            phong_config: self.phong,
+            skybox: self.skybox,
+            lights,
+            nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(contents: &str, format: SceneFormat) -> String {
+        match SceneFile::parse(contents, format) {
+            Ok(_) => panic!("The scene should be rejected:\n{}", contents),
+            Err(err) => format!("{:#}", err),
+        }
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let err = error(
+            r#"(nodes: [
+                (name: "a", model: Cube(scale: 1.0)),
+                (name: "a", model: Plane(scale: 1.0)),
+            ])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("Two nodes are named `a`"), "{}", err);
+    }
+
+    #[test]
+    fn unknown_parent() {
+        let err = error(
+            r#"(nodes: [(name: "a", model: Cube(scale: 1.0), parent: "b")])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("no node is named `b`"), "{}", err);
+
+        let err = error(
+            r#"(lights: [(parent: "b")], nodes: [(name: "a", model: Cube(scale: 1.0))])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("Light #0: no node is named `b`"), "{}", err);
+    }
+
+    #[test]
+    fn own_parent() {
+        let err = error(
+            r#"(nodes: [(name: "a", model: Cube(scale: 1.0), parent: "a")])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("Node a is its own parent"), "{}", err);
+    }
+
+    #[test]
+    fn parent_cycle() {
+        let err = error(
+            r#"(nodes: [
+                (name: "root", model: Cube(scale: 1.0)),
+                (name: "a", model: Cube(scale: 1.0), parent: "b"),
+                (name: "b", model: Cube(scale: 1.0), parent: "a"),
+            ])"#,
+            SceneFormat::Ron,
+        );
+        assert!(
+            err.contains("Nodes form a parent cycle: a -> b -> a"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        let err = error(
+            r#"(nodes: [(model: Cube(scale: 1.0), colour: (1.0, 0.0, 0.0))])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("colour"), "{}", err);
+
+        let err = error(
+            r#"{"nodes": [{"model": {"Cube": {"scale": 1.0}}, "colour": [1.0, 0.0, 0.0]}]}"#,
+            SceneFormat::Json,
+        );
+        assert!(err.contains("colour"), "{}", err);
+    }
+
+    #[test]
+    fn unsupported_model_format() {
+        let err = error(
+            r#"(nodes: [(name: "a", model: File("ferris/ferris.fbx"))])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("Node a: unsupported model format"), "{}", err);
+    }
+
+    #[test]
+    fn inverted_spot_cone() {
+        let err = error(
+            r#"(lights: [(kind: Spot(inner_angle: 40.0, outer_angle: 30.0))])"#,
+            SceneFormat::Ron,
+        );
+        assert!(err.contains("Light #0: the inner angle"), "{}", err);
+    }
+
+    #[test]
+    fn ron_and_json() {
+        let ron = r#"(
+            camera: (position: (0.0, 1.0, 2.0), yaw: -90.0, pitch: -10.0),
+            lights: [
+                (position: (1.0, 2.0, 3.0), intensity: 4.0, range: 5.0, parent: "sphere"),
+                (kind: Spot(inner_angle: 20.0, outer_angle: 30.0), shadow: ()),
+            ],
+            nodes: [
+                (model: Cube(scale: 0.5), parent: "sphere"),
+                (
+                    name: "sphere",
+                    model: Sphere(radius: 1.0, sectors: 8, stacks: 4),
+                    transform: (position: (1.0, 0.0, 0.0)),
+                    instances: [(position: (0.0, 1.0, 0.0)), (scale: (2.0, 2.0, 2.0))],
+                ),
+            ],
+        )"#;
+        let json = r#"{
+            "camera": {"position": [0.0, 1.0, 2.0], "yaw": -90.0, "pitch": -10.0},
+            "lights": [
+                {"position": [1.0, 2.0, 3.0], "intensity": 4.0, "range": 5.0, "parent": "sphere"},
+                {"kind": {"Spot": {"inner_angle": 20.0, "outer_angle": 30.0}}, "shadow": {}}
+            ],
+            "nodes": [
+                {"model": {"Cube": {"scale": 0.5}}, "parent": "sphere"},
+                {
+                    "name": "sphere",
+                    "model": {"Sphere": {"radius": 1.0, "sectors": 8, "stacks": 4}},
+                    "transform": {"position": [1.0, 0.0, 0.0]},
+                    "instances": [{"position": [0.0, 1.0, 0.0]}, {"scale": [2.0, 2.0, 2.0]}]
+                }
+            ]
+        }"#;
+
+        let scene = SceneFile::parse(ron, SceneFormat::Ron).unwrap();
+        assert_eq!(scene.nodes.len(), 2);
+        assert_eq!(scene.nodes[0].parent, Some(1));
+        assert_eq!(scene.nodes[0].instances.len(), 1);
+        assert_eq!(scene.nodes[1].parent, None);
+        assert_eq!(scene.nodes[1].transform.position, [1.0, 0.0, 0.0].into());
+        assert_eq!(scene.nodes[1].instances.len(), 2);
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.lights[0].parent, Some(1));
+        assert_eq!(scene.lights[0].light.intensity, 4.0);
+        assert_eq!(scene.lights[0].light.range, 5.0);
+        assert_eq!(scene.lights[1].light.range, f32::INFINITY);
+        assert_eq!(scene.lights[1].light.shadow, Some(ShadowConfig::default()));
+        assert!(matches!(scene.lights[1].light.kind, LightKind::Spot { .. }));
+        assert!(scene.camera.is_some());
+
+        // Both formats describe the same scene
+        let from_json = SceneFile::parse(json, SceneFormat::Json).unwrap();
+        assert_eq!(
+            format!("{:?}", scene.nodes),
+            format!("{:?}", from_json.nodes)
+        );
+        assert_eq!(
+            format!("{:?}", scene.lights),
+            format!("{:?}", from_json.lights)
+        );
+        assert_eq!(
+            format!("{:?}", scene.camera),
+            format!("{:?}", from_json.camera)
+        );
+    }
+}
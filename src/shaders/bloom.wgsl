@@ -0,0 +1,49 @@
+// Light bleeding around the bright parts of the scene
+// The bright parts are extracted at half resolution, blurred, then added back to the scene
+
+struct Bloom {
+    // Axis of the blur, (1, 0) or (0, 1)
+    direction: vec2<f32>,
+    // Brightness where the bloom starts (softened by a knee below it)
+    threshold: f32,
+    intensity: f32,
+}
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+// The blurred bright parts (composite only)
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+@fragment
+fn fs_bright(in: PostOutput) -> @location(0) vec4<f32> {
+    // The linear sampler averages the 4 texels under each half resolution one
+    let color = min(textureSample(t_input, s_input, in.uv).rgb, vec3<f32>(1000.0));
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = bloom.threshold * 0.5;
+    var soft = clamp(brightness - bloom.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.00001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_blur(in: PostOutput) -> @location(0) vec4<f32> {
+    // 9 taps gaussian, in 5 samples thanks to the linear filtering between texels
+    let offsets = vec2<f32>(1.3846153846, 3.2307692308);
+    let weights = vec3<f32>(0.2270270270, 0.3162162162, 0.0702702703);
+    // One texel along the axis, whatever the size of the frame
+    let direction = bloom.direction / vec2<f32>(textureDimensions(t_input));
+    var color = textureSample(t_input, s_input, in.uv).rgb * weights.x;
+    color += textureSample(t_input, s_input, in.uv + direction * offsets.x).rgb * weights.y;
+    color += textureSample(t_input, s_input, in.uv - direction * offsets.x).rgb * weights.y;
+    color += textureSample(t_input, s_input, in.uv + direction * offsets.y).rgb * weights.z;
+    color += textureSample(t_input, s_input, in.uv - direction * offsets.y).rgb * weights.z;
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn fs_composite(in: PostOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_input, s_input, in.uv);
+    let glow = textureSample(t_bloom, s_input, in.uv).rgb;
+    return vec4<f32>(scene.rgb + glow * bloom.intensity, scene.a);
+}
@@ -0,0 +1,51 @@
+// Full-screen triangle drawn into each face of a cubemap (`cubemap::FaceRenderer` prepends this file)
+// The fragment shaders get the direction from the center of the cube through their pixel
+
+struct FaceOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // (0, 0) is the top left corner of the face
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FaceOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FaceOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+struct Face {
+    // +X, -X, +Y, -Y, +Z, -Z
+    index: u32,
+}
+@group(1) @binding(0)
+var<uniform> face: Face;
+
+// Direction (not normalized) of a point of the face, as a cubemap sampler sees it
+fn face_direction(uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch face.index {
+        case 0u: {
+            direction = vec3<f32>(1.0, -st.y, -st.x);
+        }
+        case 1u: {
+            direction = vec3<f32>(-1.0, -st.y, st.x);
+        }
+        case 2u: {
+            direction = vec3<f32>(st.x, 1.0, st.y);
+        }
+        case 3u: {
+            direction = vec3<f32>(st.x, -1.0, -st.y);
+        }
+        case 4u: {
+            direction = vec3<f32>(st.x, -st.y, 1.0);
+        }
+        default: {
+            direction = vec3<f32>(-st.x, -st.y, -1.0);
+        }
+    }
+    return direction;
+}
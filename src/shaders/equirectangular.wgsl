@@ -0,0 +1,38 @@
+// Projects an equirectangular (latitude/longitude) image onto the faces of a cubemap
+// The image is in 32 bit floats, which can't be filtered, so texels are blended by hand
+
+@group(0) @binding(0)
+var t_equirectangular: texture_2d<f32>;
+
+// Longitudes wrap around, latitudes stop at the poles
+fn equirectangular_texel(coords: vec2<i32>, size: vec2<i32>) -> vec3<f32> {
+    let x = ((coords.x % size.x) + size.x) % size.x;
+    let y = clamp(coords.y, 0, size.y - 1);
+    return textureLoad(t_equirectangular, vec2<i32>(x, y), 0).rgb;
+}
+
+@fragment
+fn fs_main(in: FaceOutput) -> @location(0) vec4<f32> {
+    let pi = 3.14159265359;
+    let direction = normalize(face_direction(in.uv));
+    let uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * pi) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / pi,
+    );
+
+    let size = vec2<i32>(textureDimensions(t_equirectangular));
+    let texel = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(texel));
+    let blend = fract(texel);
+    let top = mix(
+        equirectangular_texel(base, size),
+        equirectangular_texel(base + vec2<i32>(1, 0), size),
+        blend.x,
+    );
+    let bottom = mix(
+        equirectangular_texel(base + vec2<i32>(0, 1), size),
+        equirectangular_texel(base + vec2<i32>(1, 1), size),
+        blend.x,
+    );
+    return vec4<f32>(mix(top, bottom, blend.y), 1.0);
+}
@@ -0,0 +1,46 @@
+// Fast approximate anti-aliasing: blurs the edges found by contrast along their direction
+// Best placed after the tone mapping, where the contrast is the one seen on screen
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luminance(textureSampleLevel(t_input, s_input, uv, 0.0).rgb);
+}
+
+@fragment
+fn fs_main(in: PostOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let center = textureSampleLevel(t_input, s_input, in.uv, 0.0);
+
+    let luma_nw = luma_at(in.uv + vec2<f32>(-1.0, -1.0) * texel);
+    let luma_ne = luma_at(in.uv + vec2<f32>(1.0, -1.0) * texel);
+    let luma_sw = luma_at(in.uv + vec2<f32>(-1.0, 1.0) * texel);
+    let luma_se = luma_at(in.uv + vec2<f32>(1.0, 1.0) * texel);
+    let luma_m = luminance(center.rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Perpendicular to the gradient, so along the edge
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    // Short edges are stretched up to 8 texels, flat areas barely move
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * 0.125, 1.0 / 128.0);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-8.0), vec2<f32>(8.0)) * texel;
+
+    let near = 0.5 * (
+        textureSampleLevel(t_input, s_input, in.uv + direction * (1.0 / 3.0 - 0.5), 0.0).rgb
+        + textureSampleLevel(t_input, s_input, in.uv + direction * (2.0 / 3.0 - 0.5), 0.0).rgb
+    );
+    let far = near * 0.5 + 0.25 * (
+        textureSampleLevel(t_input, s_input, in.uv - direction * 0.5, 0.0).rgb
+        + textureSampleLevel(t_input, s_input, in.uv + direction * 0.5, 0.0).rgb
+    );
+
+    // The wide blur went past the edge when it brings a luma the neighborhood doesn't have
+    let luma_far = luminance(far);
+    if (luma_far < luma_min || luma_far > luma_max) {
+        return vec4<f32>(near, center.a);
+    }
+    return vec4<f32>(far, center.a);
+}
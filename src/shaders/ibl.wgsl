@@ -0,0 +1,154 @@
+// Image based lighting, precomputed from the environment cubemap by `pass::ibl::Ibl`
+// (after shaders/cubemap.wgsl, each entry point fills one of the maps)
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+struct Prefilter {
+    // Of the mip level being rendered, from 0 (mirror) to 1
+    roughness: f32,
+}
+@group(0) @binding(2)
+var<uniform> prefilter: Prefilter;
+
+let PI: f32 = 3.14159265;
+let SAMPLE_COUNT: u32 = 64u;
+
+// Copies the environment, or halves the previous mip level (the linear sampler averages 2x2 texels)
+@fragment
+fn fs_downsample(in: FaceOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(in.uv);
+    return vec4<f32>(textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb, 1.0);
+}
+
+// Light reaching a lambertian surface facing each direction
+// The cosine weighted hemisphere is walked at a fixed step, on a blurred mip level
+// so that the bright spots in between steps are still counted
+@fragment
+fn fs_irradiance(in: FaceOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(face_direction(in.uv));
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    let step = 0.05;
+    // Texels about as wide as the step
+    let level = max(log2(f32(textureDimensions(t_environment).x) * step), 0.0);
+    var irradiance = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += step) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += step) {
+            let tangent = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent.x * right + tangent.y * up + tangent.z * normal;
+            let color = textureSampleLevel(t_environment, s_environment, direction, level).rgb;
+            irradiance += color * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * irradiance / count, 1.0);
+}
+
+// Van der Corput sequence, mirrored bits of `i` as a fraction
+fn radical_inverse(i: u32) -> f32 {
+    var bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// Half vector around `normal`, distributed like the GGX microfacets of `roughness`
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let half_tangent = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(normal.z) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * half_tangent.x + bitangent * half_tangent.y + normal * half_tangent.z);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+// The environment reflected by a surface of `prefilter.roughness`, seen from its normal
+// (split sum approximation, the view direction is the normal)
+// Samples read a mip level matching the solid angle they cover, which hides the noise
+@fragment
+fn fs_prefilter(in: FaceOutput) -> @location(0) vec4<f32> {
+    let normal = normalize(face_direction(in.uv));
+    let roughness = prefilter.roughness;
+    let size = f32(textureDimensions(t_environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        let half_dir = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        let light_dir = normalize(2.0 * dot(normal, half_dir) * half_dir - normal);
+        let n_dot_l = dot(normal, light_dir);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(normal, half_dir), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(SAMPLE_COUNT) * pdf);
+            var level = 0.0;
+            if (roughness > 0.0) {
+                level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            }
+            color += textureSampleLevel(t_environment, s_environment, light_dir, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(weight, 0.0001), 1.0);
+}
+
+// Scale (red) and bias (green) of f0 in the specular reflection of a uniform white environment,
+// by n·v (horizontally) and roughness (vertically)
+@fragment
+fn fs_brdf(in: FaceOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 0.0001);
+    let roughness = in.uv.y;
+    let view_dir = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+    // Schlick-GGX with the k of image based lighting
+    let k = roughness * roughness / 2.0;
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i += 1u) {
+        let half_dir = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        let light_dir = normalize(2.0 * dot(view_dir, half_dir) * half_dir - view_dir);
+        let n_dot_l = max(light_dir.z, 0.0);
+        let n_dot_h = max(half_dir.z, 0.0);
+        let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+        if (n_dot_l > 0.0) {
+            let g = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v + 0.0001);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * g_vis;
+            bias += fresnel * g_vis;
+        }
+    }
+    return vec4<f32>(scale, bias, 0.0, 1.0) / vec4<f32>(f32(SAMPLE_COUNT), f32(SAMPLE_COUNT), 1.0, 1.0);
+}
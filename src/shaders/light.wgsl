@@ -5,28 +5,19 @@ struct Globals {
     view_proj: mat4x4<f32>,
     ambient: vec4<f32>,
 };
-struct Locals {
-    position:  vec4<f32>,
-    color:  vec4<f32>,
-    normal:  vec4<f32>,
-    lights:  vec4<f32>,
-}
 // We create variables for the bind groups
 @group(0) @binding(0)
 var<uniform> globals: Globals;
-@group(1) @binding(0)
-var<uniform> locals: Locals;
-
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-@group(0) @binding(1)
-var<uniform> light: Light;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
 };
+// One instance per light, read straight from the light buffer
+struct LightInput {
+    @location(5) position: vec3<f32>,
+    @location(6) kind: u32,
+    @location(7) color: vec3<f32>,
+};
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -36,11 +27,17 @@ struct VertexOutput {
 @vertex
 fn vs_main(
     model: VertexInput,
+    light: LightInput,
 ) -> VertexOutput {
     let scale = 0.25;
     var out: VertexOutput;
     out.clip_position = globals.view_proj * vec4<f32>(model.position * scale + light.position, 1.0);
     out.color = light.color;
+    // Directional lights have no position to draw them at,
+    // collapsing every vertex to the same point discards the triangles
+    if (light.kind == 0u) {
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
     return out;
 }
 
@@ -0,0 +1,156 @@
+// Lights, shadows, materials and normal mapping, shared by the shaders of every pass
+// (the pass prepends this file, see `LightBuffer::shader`)
+
+struct Light {
+    position: vec3<f32>,
+    // 0 = directional, 1 = point, 2 = spot
+    kind: u32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>,
+    range: f32,
+    // Cosines of the inner and outer cone angles (spot lights)
+    cone: vec2<f32>,
+    // Layer of the shadow map array, -1 without shadows
+    shadow: i32,
+    shadow_bias: f32,
+    // World to shadow map clip space
+    view_proj: mat4x4<f32>,
+    normal_bias: f32,
+}
+// Only the first `count` lights are active
+// This is a storage buffer on native, the pass turns it into
+// a fixed-size uniform array where storage buffers aren't supported (WebGL)
+struct Lights {
+    count: u32,
+    items: array<Light>,
+}
+@group(0) @binding(1)
+var<storage, read> lights: Lights;
+// One layer per shadow casting light
+@group(0) @binding(3)
+var t_shadow: texture_depth_2d_array;
+@group(0) @binding(4)
+var s_shadow: sampler_comparison;
+// Image based lighting (see `Ibl`), black without an environment
+@group(0) @binding(5)
+var t_irradiance: texture_cube<f32>;
+// The environment blurred by roughness, one mip level each
+@group(0) @binding(6)
+var t_prefiltered: texture_cube<f32>;
+// `Ibl::PREFILTERED_LEVELS` - 1, for a roughness of 1
+let PREFILTERED_MAX_LEVEL: f32 = 4.0;
+// Scale and bias of f0, by n·v and roughness
+@group(0) @binding(7)
+var t_brdf_lut: texture_2d<f32>;
+@group(0) @binding(8)
+var s_ibl: sampler;
+
+// Light reaching a diffuse surface from everywhere
+// `ambient` is the one of the globals: the flat ambient color, and 1 in w when the
+// environment lights the scene instead
+fn ambient_irradiance(ambient: vec4<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let irradiance = textureSampleLevel(t_irradiance, s_ibl, normal, 0.0).rgb;
+    return mix(ambient.rgb, irradiance, ambient.w);
+}
+
+// Light from everywhere reflected towards `reflected` by a surface of `roughness`
+fn ambient_reflection(ambient: vec4<f32>, reflected: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let prefiltered = textureSampleLevel(t_prefiltered, s_ibl, reflected, roughness * PREFILTERED_MAX_LEVEL).rgb;
+    return mix(ambient.rgb, prefiltered, ambient.w);
+}
+
+// Inverse square falloff, smoothly cut off at `range` (glTF's KHR_lights_punctual)
+fn distance_attenuation(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / max(distance * distance, 0.0001);
+}
+
+// How much of the light reaches the point, from 0 (in shadow) to 1 (lit)
+// The 3x3 texels around it are compared (PCF) to soften the shadow edges
+fn shadow_factor(light: Light, world_position: vec3<f32>, world_normal: vec3<f32>) -> f32 {
+    if (light.shadow < 0) {
+        return 1.0;
+    }
+
+    let position = world_position + world_normal * light.normal_bias;
+    let clip = light.view_proj * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    // Outside of the shadow map, nothing can be in the way
+    if (abs(ndc.x) > 1.0 || abs(ndc.y) > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    let depth = ndc.z - light.shadow_bias;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y += 1) {
+        for (var x = -1; x <= 1; x += 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit += textureSampleCompareLevel(t_shadow, s_shadow, uv + offset, light.shadow, depth);
+        }
+    }
+    return lit / 9.0;
+}
+
+// What a light brings to a point: where it comes from and how much of it arrives
+struct LightSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+}
+
+fn sample_light(light: Light, world_position: vec3<f32>, world_normal: vec3<f32>) -> LightSample {
+    var out: LightSample;
+    var attenuation = 1.0;
+    if (light.kind == 0u) {
+        // Directional lights come from infinitely far away, nothing fades
+        out.direction = -light.direction;
+    } else {
+        let to_light = light.position - world_position;
+        let distance = length(to_light);
+        out.direction = to_light / distance;
+        attenuation = distance_attenuation(distance, light.range);
+
+        if (light.kind == 2u) {
+            let cos_angle = dot(-out.direction, light.direction);
+            attenuation *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+        }
+    }
+    attenuation *= shadow_factor(light, world_position, world_normal);
+    out.radiance = light.color * light.intensity * attenuation;
+    return out;
+}
+
+// Bends the interpolated normal by a normal map sample (tangent space, from 0 to 1)
+// `tangent.w` is the handedness of the bitangent, `scale` the strength of the bumps
+fn perturb_normal(normal: vec3<f32>, tangent: vec4<f32>, sampled: vec3<f32>, scale: f32) -> vec3<f32> {
+    let n = normalize(normal);
+    // Meshes without UVs have no tangent to work with
+    if (dot(tangent.xyz, tangent.xyz) < 0.000001) {
+        return n;
+    }
+    // Interpolation breaks the orthogonality, so it is restored first
+    let t = normalize(tangent.xyz - n * dot(n, tangent.xyz));
+    let b = cross(n, t) * tangent.w;
+    let bump = (sampled * 2.0 - 1.0) * vec3<f32>(scale, scale, 1.0);
+    return normalize(mat3x3<f32>(t, b, n) * bump);
+}
+
+// Factors of a material (`MaterialUniform`), each shading reads the ones it needs
+struct Material {
+    base_color: vec4<f32>,
+    specular: vec3<f32>,
+    shininess: f32,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    // Fragments with less alpha are discarded (0 unless the material is masked)
+    alpha_cutoff: f32,
+}
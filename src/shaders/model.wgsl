@@ -7,9 +7,9 @@ struct Globals {
     ambient: vec4<f32>,
 };
 struct Locals {
-    position:  vec4<f32>,
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
     color:  vec4<f32>,
-    normal:  vec4<f32>,
     lights:  vec4<f32>,
 }
 // We create variables for the bind groups
@@ -18,12 +18,7 @@ var<uniform> globals: Globals;
 @group(1) @binding(0)
 var<uniform> locals: Locals;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-@group(0) @binding(1)
-var<uniform> light: Light;
+// `Light`, `lights` and `sample_light` come from lighting.wgsl
 
 // This is the input from the vertex buffer we created
 // We get the properties from our Vertex struct here
@@ -33,6 +28,7 @@ struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 };
 // The instance buffer
 struct InstanceInput {
@@ -54,6 +50,7 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
 };
 
 @vertex
@@ -78,13 +75,22 @@ fn vs_main(
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
 
-    out.world_normal = normal_matrix * model.normal;
-    var world_position: vec4<f32> = model_matrix * (vec4<f32>(model.position, 1.0) + locals.position);
+    // The node's world matrix places the instances in the scene graph
+    let node_normal_matrix = mat3x3<f32>(
+        locals.normal[0].xyz,
+        locals.normal[1].xyz,
+        locals.normal[2].xyz,
+    );
+    out.world_normal = normalize(node_normal_matrix * normal_matrix * model.normal);
+    // Tangents follow the surface, so they are transformed like positions (without translation)
+    let world_tangent = locals.model * model_matrix * vec4<f32>(model.tangent.xyz, 0.0);
+    out.world_tangent = vec4<f32>(world_tangent.xyz, model.tangent.w);
+    var world_position: vec4<f32> = locals.model * model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
 
     // We set the "position" by using the `clip_position` property
-    // We multiply it by the camera position matrix and the instance position matrix
-    out.clip_position = globals.view_proj * model_matrix * (vec4<f32>(model.position, 1.0) + locals.position);
+    // We multiply it by the camera position matrix and the world position
+    out.clip_position = globals.view_proj * world_position;
     return out;
 }
 
@@ -94,30 +100,59 @@ fn vs_main(
 // This grabs the texture from the Local uniform
 @group(1) @binding(1)
 var t_diffuse: texture_2d<f32>;
+// Tangent space normals, flat when the material has no normal map
+@group(1) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(3)
+var<uniform> material: Material;
+// Specular color and emission, white when the material has no such map
+@group(1) @binding(4)
+var t_specular: texture_2d<f32>;
+@group(1) @binding(5)
+var t_emissive: texture_2d<f32>;
 // This grabs the sampler from the Global uniform
 @group(0) @binding(2)
 var s_diffuse: sampler;
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
     // We use the special function `textureSample` to combine the texture data with coords
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-    
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
-
-    let light_dir = normalize(light.position - in.world_position);
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * material.base_color;
+    let specular_tint = textureSample(t_specular, s_diffuse, in.tex_coords).rgb * material.specular;
+    let emissive = textureSample(t_emissive, s_diffuse, in.tex_coords).rgb * material.emissive;
+
+    // Back faces are only drawn for double-sided materials, they face the other way
+    let normal = perturb_normal(
+        select(-in.world_normal, in.world_normal, front_facing),
+        in.world_tangent,
+        textureSample(t_normal, s_diffuse, in.tex_coords).xyz,
+        material.normal_scale,
+    );
+    // After the texture samples, they have to happen in uniform control flow
+    if (object_color.a < material.alpha_cutoff) {
+        discard;
+    }
     let view_dir = normalize(globals.view_pos.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    // Light from everywhere, the environment or the flat ambient color
+    var result = ambient_irradiance(globals.ambient, normal) * object_color.xyz;
+    for (var i = 0u; i < lights.count; i += 1u) {
+        let light = lights.items[i];
+
+        let light_sample = sample_light(light, in.world_position, normal);
+        let light_dir = light_sample.direction;
+        let radiance = light_sample.radiance;
+
+        let half_dir = normalize(view_dir + light_dir);
+
+        let diffuse_strength = max(dot(normal, light_dir), 0.0);
+        let diffuse_color = radiance * diffuse_strength;
 
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+        let specular_strength = pow(max(dot(normal, half_dir), 0.0), max(material.shininess, 1.0));
+        let specular_color = specular_strength * radiance * specular_tint;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+        result += diffuse_color * object_color.xyz + specular_color;
+    }
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(result + emissive, object_color.a);
 }
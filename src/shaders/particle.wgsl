@@ -0,0 +1,75 @@
+// Particles drawn as quads facing the camera, sized and colored by their age
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+    // Axes of the screen, in world space
+    right: vec4<f32>,
+    up: vec4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Samples of the curves, evenly spaced over the life of the particles
+let CURVE_SAMPLES: u32 = 16u;
+
+struct Appearance {
+    colors: array<vec4<f32>, 16>,
+    // Four per vector
+    sizes: array<vec4<f32>, 4>,
+}
+@group(1) @binding(0)
+var<uniform> appearance: Appearance;
+@group(1) @binding(1)
+var t_sprite: texture_2d<f32>;
+@group(1) @binding(2)
+var s_sprite: sampler;
+
+struct ParticleInput {
+    @location(0) position: vec3<f32>,
+    // Both in seconds
+    @location(1) age: f32,
+    @location(2) lifetime: f32,
+}
+
+fn size_sample(index: u32) -> f32 {
+    return appearance.sizes[index / 4u][index % 4u];
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// Four vertices per particle, drawn as a triangle strip
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, particle: ParticleInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+    // From 0 at birth to 1 at death
+    let age = clamp(particle.age / particle.lifetime, 0.0, 1.0);
+    // Between the two samples around the age
+    let sample = age * f32(CURVE_SAMPLES - 1u);
+    let index = min(u32(sample), CURVE_SAMPLES - 2u);
+    let amount = sample - f32(index);
+    // Dead particles collapse to a point, nothing is drawn
+    let size = select(
+        0.0,
+        mix(size_sample(index), size_sample(index + 1u), amount),
+        particle.age < particle.lifetime,
+    );
+
+    // Particles are in world space
+    let offset = (corner * 2.0 - 1.0) * size * 0.5;
+    let position = particle.position + camera.right.xyz * offset.x + camera.up.xyz * offset.y;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.tex_coords = vec2<f32>(corner.x, 1.0 - corner.y);
+    out.color = mix(appearance.colors[index], appearance.colors[index + 1u], amount);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_sprite, s_sprite, in.tex_coords) * in.color;
+}
@@ -0,0 +1,54 @@
+// Moves the particles of a system by one step (see `ParticleForces::integrate`)
+// Reads the last state from `source` and writes the new one to `destination`
+
+struct Particle {
+    position: vec3<f32>,
+    // In seconds, dead once past the lifetime
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+}
+
+struct Simulation {
+    // Gravity in xyz, drag in w
+    gravity_drag: vec4<f32>,
+    // Noise strength and frequency, delta and time (in seconds)
+    noise_time: vec4<f32>,
+    count: vec4<u32>,
+}
+@group(0) @binding(0)
+var<uniform> simulation: Simulation;
+@group(0) @binding(1)
+var<storage, read> source: array<Particle>;
+@group(0) @binding(2)
+var<storage, read_write> destination: array<Particle>;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= simulation.count.x) {
+        return;
+    }
+
+    var particle = source[index];
+    // Dead particles wait to be spawned again by the CPU
+    if (particle.age < particle.lifetime) {
+        let frequency = simulation.noise_time.y;
+        let delta = simulation.noise_time.z;
+        let time = simulation.noise_time.w;
+        let p = particle.position;
+        let noise = vec3<f32>(
+            sin(p.y * frequency + time),
+            sin(p.z * frequency + time * 1.3),
+            sin(p.x * frequency + time * 0.7),
+        );
+        let acceleration = simulation.gravity_drag.xyz
+            + noise * simulation.noise_time.x
+            - particle.velocity * simulation.gravity_drag.w;
+
+        particle.velocity = particle.velocity + acceleration * delta;
+        particle.position = particle.position + particle.velocity * delta;
+        particle.age = particle.age + delta;
+    }
+    destination[index] = particle;
+}
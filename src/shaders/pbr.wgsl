@@ -0,0 +1,207 @@
+// Vertex shader
+
+// Define any uniforms we expect from app
+struct Globals {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient: vec4<f32>,
+};
+struct Locals {
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+    color:  vec4<f32>,
+    lights:  vec4<f32>,
+}
+// We create variables for the bind groups
+@group(0) @binding(0)
+var<uniform> globals: Globals;
+@group(1) @binding(0)
+var<uniform> locals: Locals;
+
+// `Light`, `lights` and `sample_light` come from lighting.wgsl
+
+// This is the input from the vertex buffer we created
+// We get the properties from our Vertex struct here
+// Note the index on location -- this relates to the properties placement in the buffer stride
+// e.g. 0 = 1st "set" of data, 1 = 2nd "set"
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+// The instance buffer
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+// The output we send to our fragment shader
+struct VertexOutput {
+    // This property is "builtin" (aka used to render our vertex shader)
+    @builtin(position) clip_position: vec4<f32>,
+    // These are "custom" properties we can create to pass down
+    // In this case, we pass the color down
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Reconstruct the matrix from the flattened/raw data
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+
+    // We define the output we want to send over to frag shader
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+
+    // The node's world matrix places the instances in the scene graph
+    let node_normal_matrix = mat3x3<f32>(
+        locals.normal[0].xyz,
+        locals.normal[1].xyz,
+        locals.normal[2].xyz,
+    );
+    out.world_normal = normalize(node_normal_matrix * normal_matrix * model.normal);
+    // Tangents follow the surface, so they are transformed like positions (without translation)
+    let world_tangent = locals.model * model_matrix * vec4<f32>(model.tangent.xyz, 0.0);
+    out.world_tangent = vec4<f32>(world_tangent.xyz, model.tangent.w);
+    var world_position: vec4<f32> = locals.model * model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+
+    // We set the "position" by using the `clip_position` property
+    // We multiply it by the camera position matrix and the world position
+    out.clip_position = globals.view_proj * world_position;
+    return out;
+}
+
+// Fragment shader
+
+// Material maps, missing ones are white so only the factors remain
+@group(1) @binding(1)
+var t_base_color: texture_2d<f32>;
+// Tangent space normals
+@group(1) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(3)
+var<uniform> material: Material;
+// Roughness in green, metallic in blue (glTF)
+@group(1) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(5)
+var t_occlusion: texture_2d<f32>;
+@group(1) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(2)
+var s_material: sampler;
+
+let PI: f32 = 3.14159265;
+
+// GGX / Trowbridge-Reitz normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+// Schlick-GGX geometry term, for one direction
+fn geometry_schlick_ggx(n_dot_x: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// Smith's method: shadowing (towards the light) and masking (towards the viewer)
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    return geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Averaged over the microfacets, rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let f90 = max(vec3<f32>(1.0 - roughness), f0);
+    return f0 + (f90 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = textureSample(t_base_color, s_material, in.tex_coords) * material.base_color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_material, in.tex_coords);
+    // Perfectly smooth surfaces make the specular highlight vanish
+    let roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+    let metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+    let occlusion = 1.0 + material.occlusion_strength
+        * (textureSample(t_occlusion, s_material, in.tex_coords).r - 1.0);
+    let emissive = textureSample(t_emissive, s_material, in.tex_coords).rgb * material.emissive;
+
+    // Back faces are only drawn for double-sided materials, they face the other way
+    let normal = perturb_normal(
+        select(-in.world_normal, in.world_normal, front_facing),
+        in.world_tangent,
+        textureSample(t_normal, s_material, in.tex_coords).xyz,
+        material.normal_scale,
+    );
+    // After the texture samples, they have to happen in uniform control flow
+    if (base_color.a < material.alpha_cutoff) {
+        discard;
+    }
+    let view_dir = normalize(globals.view_pos.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+
+    // Dielectrics reflect about 4% of the light, metals reflect their color
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
+
+    var radiance_out = vec3<f32>(0.0, 0.0, 0.0);
+    for (var i = 0u; i < lights.count; i += 1u) {
+        let light_sample = sample_light(lights.items[i], in.world_position, normal);
+        let light_dir = light_sample.direction;
+        let half_dir = normalize(view_dir + light_dir);
+        let n_dot_l = max(dot(normal, light_dir), 0.0);
+
+        // Cook-Torrance specular
+        let d = distribution_ggx(max(dot(normal, half_dir), 0.0), roughness);
+        let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        let f = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+        let specular = d * g * f / max(4.0 * n_dot_v * n_dot_l, 0.0001);
+
+        // What isn't reflected is diffused, except by metals
+        let diffuse = (vec3<f32>(1.0) - f) * (1.0 - metallic) * base_color.rgb / PI;
+
+        radiance_out += (diffuse + specular) * light_sample.radiance * n_dot_l;
+    }
+
+    // Light from everywhere (split sum approximation), the environment or the flat ambient color
+    let f_ambient = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let brdf = textureSampleLevel(t_brdf_lut, s_ibl, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let reflected = reflect(-view_dir, normal);
+    let ambient_specular = ambient_reflection(globals.ambient, reflected, roughness)
+        * (f_ambient * brdf.x + brdf.y);
+    let ambient_diffuse = (vec3<f32>(1.0) - f_ambient) * (1.0 - metallic)
+        * ambient_irradiance(globals.ambient, normal) * base_color.rgb;
+    let ambient = (ambient_diffuse + ambient_specular) * occlusion;
+
+    return vec4<f32>(ambient + radiance_out + emissive, base_color.a);
+}
@@ -0,0 +1,28 @@
+// Full-screen triangle shared by the post effects (`post::effect_shader` prepends this file)
+// Each effect reads the output of the previous step (the HDR scene for the first one)
+
+struct PostOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // (0, 0) is the top left corner of the screen
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> PostOutput {
+    // A single triangle covering the screen, the rasterizer clips what's outside
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: PostOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+
+// Relative luminance of a linear color
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
@@ -0,0 +1,76 @@
+// Depth only pass, from the point of view of a light
+// Masked materials also run `fs_mask`, so their cut out parts don't cast shadows
+
+struct Locals {
+    model: mat4x4<f32>,
+    normal: mat4x4<f32>,
+    color:  vec4<f32>,
+    lights:  vec4<f32>,
+}
+// Same as in lighting.wgsl
+struct Material {
+    base_color: vec4<f32>,
+    specular: vec3<f32>,
+    shininess: f32,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    occlusion_strength: f32,
+    normal_scale: f32,
+    alpha_cutoff: f32,
+}
+// World to shadow map clip space
+@group(0) @binding(0)
+var<uniform> light_view_proj: mat4x4<f32>;
+@group(0) @binding(1)
+var s_base_color: sampler;
+// The bindings every scene shader starts its local bind group with
+@group(1) @binding(0)
+var<uniform> locals: Locals;
+@group(1) @binding(1)
+var t_base_color: texture_2d<f32>;
+@group(1) @binding(3)
+var<uniform> material: Material;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+};
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = light_view_proj * locals.model * model_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    return out;
+}
+
+// Alpha test of masked materials, same as the scene shaders
+@fragment
+fn fs_mask(in: VertexOutput) {
+    let alpha = textureSample(t_base_color, s_base_color, in.tex_coords).a * material.base_color.a;
+    if (alpha < material.alpha_cutoff) {
+        discard;
+    }
+}
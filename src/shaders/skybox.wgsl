@@ -0,0 +1,35 @@
+// The environment cubemap drawn behind everything
+// A full-screen triangle on the far plane, only visible where nothing was drawn
+
+struct Sky {
+    // Inverse of the projection times the view rotation (the sky never moves with the camera)
+    inv_view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+@group(0) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(2)
+var s_sky: sampler;
+
+struct SkyOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Point of the far plane, relative to the camera (divided by `w` per pixel)
+    @location(0) far: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SkyOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 1.0, 1.0);
+    var out: SkyOutput;
+    out.clip_position = position;
+    out.far = sky.inv_view_proj * position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: SkyOutput) -> @location(0) vec4<f32> {
+    let direction = in.far.xyz / in.far.w;
+    return vec4<f32>(textureSample(t_sky, s_sky, direction).rgb, 1.0);
+}
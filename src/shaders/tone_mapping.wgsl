@@ -0,0 +1,38 @@
+// Maps the HDR colors of the scene to the [0, 1] range of the screen
+
+struct ToneMapping {
+    exposure: f32,
+    // 0 = ACES, 1 = Reinhard, 2 = none (only clamped, `ToneMapping::copy`)
+    curve: u32,
+}
+@group(0) @binding(2)
+var<uniform> tone_mapping: ToneMapping;
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return (color * (a * color + b)) / (color * (c * color + d) + e);
+}
+
+// Scales the luminance so it never reaches 1, keeping the hue
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    let l = luminance(color);
+    return color / (1.0 + l);
+}
+
+@fragment
+fn fs_main(in: PostOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_input, s_input, in.uv);
+    let color = hdr.rgb * tone_mapping.exposure;
+    var mapped = color;
+    if (tone_mapping.curve == 0u) {
+        mapped = aces(color);
+    } else if (tone_mapping.curve == 1u) {
+        mapped = reinhard(color);
+    }
+    return vec4<f32>(clamp(mapped, vec3<f32>(0.0), vec3<f32>(1.0)), hdr.a);
+}
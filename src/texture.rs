@@ -6,13 +6,15 @@ use image::GenericImageView;
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
+    // Of the first mip level (6 layers for cubemaps)
+    pub size: wgpu::Extent3d,
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
 
-    // Create a new texture to contain the depth information of scene
-    pub fn create_depth_texture(
+    // Create a color texture the scene can be rendered into (and copied out of)
+    pub fn create_render_target(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
         label: &str,
@@ -22,31 +24,91 @@ impl Texture {
             height: config.height,
             depth_or_array_layers: 1,
         };
-        let desc = wgpu::TextureDescriptor {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+        }
+    }
+
+    // Create a texture of the render graph (see `graph::AttachmentDesc`)
+    pub fn create_attachment(
+        device: &wgpu::Device,
+        (width, height): (u32, u32),
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        usage: wgpu::TextureUsages,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(&desc);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let _sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
+
+        Self {
+            texture,
+            view,
+            size,
+        }
+    }
+
+    // Create an empty cubemap, its view covers the six faces (+X, -X, +Y, -Y, +Z, -Z)
+    pub fn create_cube(
+        device: &wgpu::Device,
+        size: u32,
+        format: wgpu::TextureFormat,
+        mip_level_count: u32,
+        usage: wgpu::TextureUsages,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
             ..Default::default()
         });
 
-        Self { texture, view }
+        Self {
+            texture,
+            view,
+            size,
+        }
     }
 
     // Load an image from bytes then generate texture
@@ -66,6 +128,64 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(
+            device,
+            queue,
+            img,
+            label,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    // Same as `from_bytes`, for data that isn't a color (Rgba8Unorm instead of sRGB)
+    pub fn from_bytes_linear(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+    }
+
+    // A single pixel texture, stands in for the maps a material doesn't have
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Self {
+        let img =
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba(color)));
+        Self::from_image_with_format(
+            device,
+            queue,
+            &img,
+            Some(label),
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+        .expect("A single pixel always fits in a texture")
+    }
+
+    // A normal map that leaves the normals as they are (straight up in tangent space)
+    pub fn flat_normal(device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Self {
+        Self::from_color(device, queue, [128, 128, 255, 255], label)
+    }
+
+    // Generate texture from image data, in a 8 bit RGBA `format`
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -81,7 +201,7 @@ impl Texture {
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
@@ -112,6 +232,10 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self { texture, view })
+        Ok(Self {
+            texture,
+            view,
+            size,
+        })
     }
 }
@@ -52,11 +52,11 @@ impl Window {
             .expect("Couldn't append canvas to document body.");
     }
 
-    pub fn new() -> Self {
+    pub fn new(title: &str) -> Self {
         // TODO: Add size
         let event_loop = EventLoop::new();
         let window = window::WindowBuilder::new()
-            .with_title("MJOLNIR")
+            .with_title(title)
             .build(&event_loop)
             .unwrap();
 
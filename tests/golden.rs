@@ -0,0 +1,675 @@
+//! Golden image tests
+//!
+//! Canned scenes are rendered headlessly (fixed size, camera and time) and compared
+//! to the references in `tests/golden/`. On failure the actual render and a diff image
+//! are written next to the other test artifacts (`target/tmp/golden/`).
+//!
+//! Run with `MJOLNIR_BLESS=1` to (re)generate the references after an intended change.
+//! A missing graphics adapter fails the tests, set `MJOLNIR_SKIP_GOLDEN=1` to skip them
+//! on machines that can't render.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+use image::{Rgba, RgbaImage};
+use mjolnir::{
+    post::{BloomConfig, PostEffectConfig, ToneMappingConfig, ToneMappingCurve},
+    Burst, Camera, Curve, Duration, EmitterShape, Engine, EngineBuilder, Instance, Light,
+    ModelSource, NodeDescriptor, ParticleAppearance, ParticleBlend, ParticleEmitter,
+    ParticleForces, ParticleSystemDescriptor, PhongConfig, Shading, ShadowConfig, SkyboxSource,
+    Transform,
+};
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 256;
+
+// Perceptual (YIQ) distance above which two pixels differ, from 0 to 1
+const PIXEL_THRESHOLD: f32 = 0.1;
+// Share of pixels allowed to differ, rasterizers don't all agree on edges
+const MAX_DIFF_RATIO: f32 = 0.01;
+
+// Tests run in parallel, but some (software) adapters don't like sharing the GPU
+static GPU: Mutex<()> = Mutex::new(());
+
+#[test]
+fn primitives() {
+    check("primitives", primitives_scene(PhongConfig::default()));
+}
+
+// Same scene, each mesh drawn from its own buffers as on WebGL
+#[test]
+fn primitives_direct() {
+    let builder = primitives_scene(PhongConfig {
+        indirect_draws: false,
+        ..Default::default()
+    });
+    check("primitives_direct", builder);
+}
+
+#[test]
+fn primitives_msaa() {
+    let builder = Engine::builder()
+        .with_phong_config(PhongConfig {
+            msaa: true,
+            ..Default::default()
+        })
+        .with_camera(Camera::new((0.0, 1.5, 4.0), Deg(-90.0), Deg(-20.0)))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.5 },
+            Transform {
+                rotation: Quaternion::from_angle_y(Deg(30.0)),
+                ..Default::default()
+            },
+        ));
+
+    check("primitives_msaa", builder);
+}
+
+#[test]
+fn avocado() {
+    check("avocado", avocado_scene(PhongConfig::default()));
+}
+
+#[test]
+fn avocado_pbr() {
+    let builder = avocado_scene(PhongConfig {
+        shading: Shading::Pbr,
+        ..Default::default()
+    });
+
+    check("avocado_pbr", builder);
+}
+
+#[test]
+fn avocado_post() {
+    let builder = avocado_scene(PhongConfig {
+        post_effects: vec![
+            PostEffectConfig::Bloom(BloomConfig {
+                threshold: 0.8,
+                ..Default::default()
+            }),
+            PostEffectConfig::ToneMapping(ToneMappingConfig {
+                curve: ToneMappingCurve::Reinhard,
+                exposure: 1.5,
+            }),
+            PostEffectConfig::Fxaa,
+        ],
+        ..Default::default()
+    });
+
+    check("avocado_post", builder);
+}
+
+#[test]
+fn skybox() {
+    let builder = Engine::builder()
+        .with_phong_config(PhongConfig {
+            msaa: true,
+            ..Default::default()
+        })
+        .with_camera(Camera::new((0.0, 0.5, 4.0), Deg(-75.0), Deg(0.0)))
+        .with_skybox(Some(SkyboxSource::Equirectangular(
+            Path::new("skybox").join("sky.hdr"),
+        )))
+        .with_node(node(ModelSource::Cube { scale: 0.5 }, Transform::default()));
+
+    check("skybox", builder);
+}
+
+#[test]
+fn ferris() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 1.0, 3.0), Deg(-90.0), Deg(-15.0)))
+        .with_light(Light::new([1.0, 2.0, 2.0], [1.0, 1.0, 1.0]).with_intensity(9.0))
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::File(Path::new("ferris").join("ferris.obj")),
+            Transform::default(),
+        ));
+
+    check("ferris", builder);
+}
+
+// Several nodes sharing one material, each made of meshes sharing it too,
+// so their draws are merged into one batch per node
+#[test]
+fn shared_materials() {
+    check(
+        "shared_materials",
+        shared_materials_scene(PhongConfig::default()),
+    );
+}
+
+#[test]
+fn shared_materials_direct() {
+    let builder = shared_materials_scene(PhongConfig {
+        indirect_draws: false,
+        ..Default::default()
+    });
+    check("shared_materials_direct", builder);
+}
+
+// Two blended quads (the nearest one first in the file) in front of a masked one,
+// the blended ones must be drawn back to front and the masked one cut where its alpha is low
+#[test]
+fn alpha_modes() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 0.0, 3.0), Deg(-90.0), Deg(0.0)))
+        .with_light(Light::new([0.0, 1.0, 3.0], [1.0, 1.0, 1.0]).with_intensity(2.0))
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::File(Path::new("alpha").join("alpha.gltf")),
+            Transform::default(),
+        ))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.3 },
+            Transform::from_position(Vector3::new(0.0, 0.0, -1.5)),
+        ));
+
+    check("alpha_modes", builder);
+}
+
+// The light models don't need any node to be drawn
+#[test]
+fn light_models() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 0.0, 3.0), Deg(-90.0), Deg(0.0)))
+        .with_light(Light::new([-0.8, 0.0, 0.0], [1.0, 0.3, 0.2]))
+        .with_light(Light::new([0.8, 0.0, 0.0], [0.2, 0.3, 1.0]));
+
+    check("light_models", builder);
+}
+
+// One light of each kind: a dim directional light, a spot light with a hard edge
+// (equal angles) and a bright point light whose range ends before the far side of the floor
+#[test]
+fn light_kinds() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 2.0, 3.5), Deg(-90.0), Deg(-35.0)))
+        .with_light(Light::directional([-0.3, -1.0, -0.5], [0.4, 0.4, 1.0]).with_intensity(0.3))
+        .with_light(
+            Light::spot(
+                [-0.8, 1.5, 0.0],
+                [0.0, -1.0, 0.0],
+                [1.0, 0.9, 0.5],
+                Deg(25.0),
+                Deg(25.0),
+            )
+            .with_intensity(4.0),
+        )
+        .with_light(
+            Light::new([0.9, 0.4, 0.3], [1.0, 0.3, 0.2])
+                .with_intensity(2.0)
+                .with_range(1.5),
+        )
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::Plane { scale: 2.0 },
+            Transform {
+                position: Vector3::new(0.0, -2.5, 0.0),
+                rotation: Quaternion::from_angle_x(Deg(-90.0)),
+                ..Default::default()
+            },
+        ))
+        .with_node(node(
+            ModelSource::Sphere {
+                radius: 0.3,
+                sectors: 36,
+                stacks: 18,
+            },
+            Transform::from_position(Vector3::new(0.0, -0.2, -0.5)),
+        ));
+
+    check("light_kinds", builder);
+}
+
+// A directional and a spot light casting shadows onto a floor
+#[test]
+fn shadows() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 1.5, 3.5), Deg(-90.0), Deg(-25.0)))
+        .with_light(
+            Light::directional([0.6, -1.0, -0.4], [1.0, 0.9, 0.8])
+                .with_intensity(0.6)
+                .with_shadow(ShadowConfig::default()),
+        )
+        .with_light(
+            Light::spot(
+                [0.8, 1.5, 0.3],
+                [0.0, -1.0, 0.0],
+                [0.6, 0.8, 1.0],
+                Deg(20.0),
+                Deg(30.0),
+            )
+            .with_intensity(6.0)
+            .with_shadow(ShadowConfig::default()),
+        )
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::Plane { scale: 2.0 },
+            Transform {
+                position: Vector3::new(0.0, -2.5, 0.0),
+                rotation: Quaternion::from_angle_x(Deg(-90.0)),
+                ..Default::default()
+            },
+        ))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.3 },
+            Transform::from_position(Vector3::new(-0.7, 0.1, 0.0)),
+        ))
+        .with_node(node(
+            ModelSource::Sphere {
+                radius: 0.3,
+                sectors: 36,
+                stacks: 18,
+            },
+            Transform::from_position(Vector3::new(0.8, 0.2, 0.3)),
+        ));
+
+    check("shadows", builder);
+}
+
+// The alpha modes quads in front of a wall lit by a shadow casting light:
+// the blended quads cast no shadow and the masked one only where it isn't cut
+#[test]
+fn alpha_shadows() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 0.0, 3.5), Deg(-90.0), Deg(0.0)))
+        .with_light(
+            Light::directional([0.5, -0.4, -1.0], [1.0, 1.0, 1.0])
+                .with_intensity(0.8)
+                .with_shadow(ShadowConfig::default()),
+        )
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::File(Path::new("alpha").join("alpha.gltf")),
+            Transform {
+                position: Vector3::new(-0.3, 0.3, 0.5),
+                scale: Vector3::new(0.6, 0.6, 0.6),
+                ..Default::default()
+            },
+        ))
+        .with_node(node(
+            // The plane is `scale` in front of its origin, this puts it at z = -1.6
+            ModelSource::Plane { scale: 1.5 },
+            Transform::from_position(Vector3::new(0.0, 0.0, -3.1)),
+        ));
+
+    check("alpha_shadows", builder);
+}
+
+#[test]
+fn particles() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 1.0, 4.0), Deg(-90.0), Deg(-15.0)))
+        .with_node(node(ModelSource::Cube { scale: 0.5 }, Transform::default()))
+        .with_particle_system(ParticleSystemDescriptor {
+            emitter: ParticleEmitter {
+                transform: Transform::from_position(Vector3::new(-1.2, -0.5, 0.0)),
+                shape: EmitterShape::Box {
+                    half_extents: [0.4, 0.3, 0.3],
+                },
+                bursts: vec![burst(3)],
+                ..Default::default()
+            },
+            appearance: ParticleAppearance {
+                size: Curve::linear(0.8, 0.4),
+                ..Default::default()
+            },
+            max_particles: 3,
+            ..Default::default()
+        })
+        .with_particle_system(ParticleSystemDescriptor {
+            emitter: ParticleEmitter {
+                transform: Transform::from_position(Vector3::new(0.6, 0.5, -1.0)),
+                shape: EmitterShape::Sphere { radius: 0.6 },
+                bursts: vec![burst(3)],
+                ..Default::default()
+            },
+            appearance: ParticleAppearance {
+                blend: ParticleBlend::Alpha,
+                size: Curve::linear(1.0, 1.5),
+                color: Curve::linear([0.8, 0.8, 0.8, 1.0], [0.2, 0.2, 0.2, 0.0]),
+            },
+            max_particles: 3,
+            ..Default::default()
+        });
+
+    check("particles", builder);
+}
+
+// Gravity and noise over a second
+#[test]
+fn particle_forces() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 0.0, 6.0), Deg(-90.0), Deg(0.0)))
+        .with_particle_system(ParticleSystemDescriptor {
+            emitter: ParticleEmitter {
+                transform: Transform::from_position(Vector3::new(-0.5, 0.0, -2.0)),
+                shape: EmitterShape::Box {
+                    half_extents: [1.5, 0.5, 0.0],
+                },
+                bursts: vec![burst(4)],
+                speed: 0.0..0.0,
+                lifetime: 10.0..10.0,
+                ..Default::default()
+            },
+            appearance: ParticleAppearance {
+                size: Curve::constant(0.5),
+                color: Curve::constant([1.0, 1.0, 1.0, 1.0]),
+                ..Default::default()
+            },
+            forces: ParticleForces {
+                gravity: [0.0, -2.0, 0.0],
+                drag: 0.5,
+                noise: 4.0,
+                noise_frequency: 2.0,
+            },
+            max_particles: 4,
+            ..Default::default()
+        });
+
+    check_after(
+        "particle_forces",
+        builder,
+        &[Duration::from_millis(100); 10],
+    );
+}
+
+// A cone following a moved node, spawning steadily and in repeated bursts,
+// particles growing then shrinking and changing color through several keys
+#[test]
+fn particle_emitters() {
+    let builder = Engine::builder()
+        .with_camera(Camera::new((0.0, 1.0, 5.0), Deg(-90.0), Deg(-10.0)))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.2 },
+            Transform {
+                position: Vector3::new(-1.0, -1.0, 0.0),
+                rotation: Quaternion::from_angle_z(Deg(-30.0)),
+                ..Default::default()
+            },
+        ))
+        .with_particle_system(ParticleSystemDescriptor {
+            parent: Some(0),
+            emitter: ParticleEmitter {
+                transform: Transform::from_position(Vector3::new(0.0, 0.2, 0.0)),
+                shape: EmitterShape::Cone {
+                    radius: 0.1,
+                    angle: 15.0,
+                },
+                rate: 20.0,
+                bursts: vec![Burst {
+                    time: 0.0,
+                    count: 10,
+                    interval: Some(0.5),
+                }],
+                speed: 2.0..3.0,
+                lifetime: 2.0..2.0,
+                ..Default::default()
+            },
+            appearance: ParticleAppearance {
+                size: Curve::new(vec![(0.0, 0.1), (0.3, 0.4), (1.0, 0.0)]),
+                color: Curve::new(vec![
+                    (0.0, [1.0, 1.0, 1.0, 1.0]),
+                    (0.2, [1.0, 0.6, 0.1, 1.0]),
+                    (1.0, [0.2, 0.2, 1.0, 0.5]),
+                ]),
+                ..Default::default()
+            },
+            forces: ParticleForces {
+                gravity: [0.0, -1.0, 0.0],
+                ..Default::default()
+            },
+            max_particles: 100,
+            ..Default::default()
+        });
+
+    check_after(
+        "particle_emitters",
+        builder,
+        &[Duration::from_millis(100); 12],
+    );
+}
+
+fn avocado_scene(phong_config: PhongConfig) -> EngineBuilder {
+    Engine::builder()
+        .with_phong_config(phong_config)
+        .with_camera(Camera::new((0.0, 0.5, 2.5), Deg(-90.0), Deg(-5.0)))
+        .with_light(Light::new([1.0, 2.0, 2.0], [1.0, 1.0, 1.0]).with_intensity(9.0))
+        .with_light_model(None)
+        .with_node(node(
+            ModelSource::File(Path::new("avocado").join("Avocado.gltf")),
+            Transform {
+                scale: Vector3::new(20.0, 20.0, 20.0),
+                ..Default::default()
+            },
+        ))
+}
+
+fn shared_materials_scene(phong_config: PhongConfig) -> EngineBuilder {
+    let ferris = |x: f32, angle: f32| {
+        node(
+            ModelSource::File(Path::new("ferris").join("ferris.obj")),
+            Transform {
+                position: Vector3::new(x, 0.0, 0.0),
+                rotation: Quaternion::from_angle_y(Deg(angle)),
+                scale: Vector3::new(0.6, 0.6, 0.6),
+            },
+        )
+    };
+    Engine::builder()
+        .with_phong_config(phong_config)
+        .with_camera(Camera::new((0.0, 1.0, 3.5), Deg(-90.0), Deg(-15.0)))
+        .with_light(Light::new([1.0, 2.0, 2.0], [1.0, 1.0, 1.0]).with_intensity(9.0))
+        .with_light_model(None)
+        .with_node(ferris(-1.2, 30.0))
+        .with_node(ferris(0.0, 0.0))
+        .with_node(ferris(1.2, -30.0))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.2 },
+            Transform::from_position(Vector3::new(-0.6, -0.4, 0.5)),
+        ))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.2 },
+            Transform::from_position(Vector3::new(0.6, -0.4, 0.5)),
+        ))
+}
+
+fn primitives_scene(phong_config: PhongConfig) -> EngineBuilder {
+    Engine::builder()
+        .with_phong_config(phong_config)
+        .with_camera(Camera::new((0.0, 1.5, 4.0), Deg(-90.0), Deg(-20.0)))
+        .with_node(node(
+            ModelSource::Sphere {
+                radius: 0.5,
+                sectors: 36,
+                stacks: 18,
+            },
+            Transform::from_position(Vector3::new(-1.5, 0.0, 0.0)),
+        ))
+        .with_node(node(
+            ModelSource::Cube { scale: 0.5 },
+            Transform {
+                rotation: Quaternion::from_angle_y(Deg(30.0)),
+                ..Default::default()
+            },
+        ))
+        .with_node(node(
+            ModelSource::Plane { scale: 0.5 },
+            Transform::from_position(Vector3::new(1.5, 0.0, -0.5)),
+        ))
+}
+
+fn node(model: ModelSource, transform: Transform) -> NodeDescriptor {
+    NodeDescriptor {
+        model,
+        transform,
+        parent: None,
+        instances: vec![Instance {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::from_angle_y(Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }],
+    }
+}
+
+// Spawned as soon as the system is created
+fn burst(count: u32) -> Burst {
+    Burst {
+        time: 0.0,
+        count,
+        interval: None,
+    }
+}
+
+// Renders the scene and compares it to its reference
+fn check(name: &str, builder: EngineBuilder) {
+    // Time stays at zero so animations always show their first frame
+    check_after(name, builder, &[Duration::ZERO]);
+}
+
+// Same as `check`, after updating the scene with each of `steps` (fixed, so it is reproducible)
+fn check_after(name: &str, builder: EngineBuilder, steps: &[Duration]) {
+    if std::env::var_os("MJOLNIR_SKIP_GOLDEN").is_some() {
+        eprintln!("MJOLNIR_SKIP_GOLDEN is set, skipping golden image `{name}`");
+        return;
+    }
+
+    let _gpu = GPU.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    assert!(
+        has_adapter(),
+        "No graphics adapter available to render `{name}`, set MJOLNIR_SKIP_GOLDEN=1 to skip the golden images"
+    );
+
+    let actual = pollster::block_on(async {
+        let mut engine = builder.build_headless(WIDTH, HEIGHT).await?;
+        for &step in steps {
+            engine.update(step);
+        }
+        engine.capture(false).await
+    })
+    .expect("Couldn't render the scene")
+    .color;
+
+    let reference = golden_dir().join(format!("{name}.png"));
+    if std::env::var_os("MJOLNIR_BLESS").is_some() {
+        actual
+            .save(&reference)
+            .expect("Couldn't save the reference");
+        return;
+    }
+
+    let expected = image::open(&reference)
+        .unwrap_or_else(|err| {
+            panic!(
+                "Couldn't open {} ({err}), run with MJOLNIR_BLESS=1 to create it",
+                reference.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "Reference `{name}` doesn't have the rendered size"
+    );
+
+    let (diff, mismatched) = diff_images(&expected, &actual);
+    let ratio = mismatched as f32 / (WIDTH * HEIGHT) as f32;
+    if ratio > MAX_DIFF_RATIO {
+        let output = output_dir();
+        std::fs::create_dir_all(&output).unwrap();
+        let actual_path = output.join(format!("{name}.actual.png"));
+        let diff_path = output.join(format!("{name}.diff.png"));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+
+        panic!(
+            "`{name}` differs from its reference on {:.2}% of the pixels (max {:.2}%)\n\
+             actual: {}\n\
+             diff: {}",
+            ratio * 100.0,
+            MAX_DIFF_RATIO * 100.0,
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+fn has_adapter() -> bool {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .or_else(|| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: true,
+        }))
+    })
+    .is_some()
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden")
+}
+
+// Returns the diff image (mismatches in red over a faded reference)
+// and the number of mismatched pixels
+fn diff_images(expected: &RgbaImage, actual: &RgbaImage) -> (RgbaImage, usize) {
+    // Largest possible YIQ distance, between black and white
+    const MAX_DELTA: f32 = 35215.0;
+    let threshold = MAX_DELTA * PIXEL_THRESHOLD * PIXEL_THRESHOLD;
+
+    let mut mismatched = 0;
+    let mut diff = RgbaImage::new(expected.width(), expected.height());
+    for ((expected, actual), diff) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        if yiq_delta(expected, actual) > threshold {
+            mismatched += 1;
+            *diff = Rgba([255, 0, 0, 255]);
+        } else {
+            let luma = 255.0 - (255.0 - yiq(expected).0) * 0.1;
+            *diff = Rgba([luma as u8, luma as u8, luma as u8, 255]);
+        }
+    }
+
+    (diff, mismatched)
+}
+
+// Squared distance in the YIQ color space, closer to perceived differences than RGB
+fn yiq_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let (ay, ai, aq) = yiq(a);
+    let (by, bi, bq) = yiq(b);
+    let (y, i, q) = (ay - by, ai - bi, aq - bq);
+
+    0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q
+}
+
+// Pixels are blended over white so transparency shows up as a difference
+fn yiq(pixel: &Rgba<u8>) -> (f32, f32, f32) {
+    let alpha = pixel[3] as f32 / 255.0;
+    let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| 255.0 + (c as f32 - 255.0) * alpha);
+
+    (
+        r * 0.298_895_3 + g * 0.586_622_5 + b * 0.114_482_23,
+        r * 0.595_978 - g * 0.274_176_1 - b * 0.321_801_9,
+        r * 0.211_470_17 - g * 0.522_617_1 + b * 0.311_146_94,
+    )
+}